@@ -1,9 +1,15 @@
-use std::{fs::read_to_string, path::PathBuf};
+use std::{
+    fs::read_to_string,
+    io::{IsTerminal, stdout},
+    path::PathBuf,
+};
 
 mod repl;
 
 use clap::Parser;
-use marble::{evaluate_file, evaluate_string, source::Source};
+use marble::{
+    diagnostic::Renderer, error::AnnotatedError, evaluate_file, evaluate_string, source::Source,
+};
 use repl::input;
 
 /// Marble interpreter
@@ -29,7 +35,7 @@ fn repl() {
     for line in input() {
         match evaluate_string(&line, PathBuf::default()) {
             Ok(value) => println!("{value}"),
-            Err(e) => println!("{}", e.of_source(&Source::new(&line))),
+            Err(e) => print!("{}", render(&e, &line)),
         }
     }
 }
@@ -37,9 +43,12 @@ fn repl() {
 fn run_file(file: &PathBuf) {
     match evaluate_file(file) {
         Ok(value) => println!("{value}"),
-        Err(e) => println!(
-            "{}",
-            e.of_source(&Source::new(&read_to_string(file).unwrap()))
-        ),
+        Err(e) => print!("{}", render(&e, &read_to_string(file).unwrap())),
     }
 }
+
+fn render(error: &AnnotatedError, code: &str) -> String {
+    Renderer::new()
+        .with_colour(stdout().is_terminal())
+        .render(error, &Source::new(code))
+}
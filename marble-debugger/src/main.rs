@@ -9,7 +9,7 @@ use dap::{
     prelude::*,
     types::{Capabilities, OutputEventCategory},
 };
-use marble::source::Source;
+use marble::{diagnostic::Renderer, source::Source};
 
 fn main() {
     let output = BufWriter::new(std::io::stdout());
@@ -83,7 +83,7 @@ fn main() {
             server
                 .send_event(Event::Output(OutputEventBody {
                     category: Some(OutputEventCategory::Stderr),
-                    output: error.of_source(&source),
+                    output: Renderer::new().render(&error, &source),
                     ..Default::default()
                 }))
                 .unwrap();
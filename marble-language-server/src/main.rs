@@ -1,8 +1,6 @@
 use dashmap::DashMap;
 use line_index::{LineCol, TextRange};
-use marble::compiler::Compiler;
-use marble::error::AnnotatedError;
-use marble::expr::ExprRef;
+use marble::compiler::{Compilation, Compiler};
 use marble::scanner::Scanner;
 use marble::source::Source;
 use tower_lsp::jsonrpc::Error;
@@ -12,7 +10,7 @@ use tower_lsp::{Client, LanguageServer, LspService, Server};
 #[derive(Debug)]
 struct Backend {
     client: Client,
-    document_map: DashMap<Url, (String, Result<ExprRef, AnnotatedError>)>,
+    document_map: DashMap<Url, (String, Compilation)>,
 }
 
 #[tower_lsp::async_trait]
@@ -91,29 +89,33 @@ impl Backend {
 
         let mut compiler = Compiler::new(&source, scanner);
         compiler.with_bindings(Compiler::default_bindings());
-        let expr = compiler.compile();
-
-        if let Err(error) = &expr {
-            let msg = error.of_source(&source);
-
-            let diagnostic = Diagnostic {
-                message: msg,
-                severity: Some(DiagnosticSeverity::ERROR),
+        let compilation = compiler.compile_all();
+
+        let errors = compilation
+            .errors
+            .iter()
+            .map(|error| (error, DiagnosticSeverity::ERROR));
+        let warnings = compilation
+            .warnings
+            .iter()
+            .map(|warning| (warning, DiagnosticSeverity::WARNING));
+
+        // Publishing no diagnostics clears the ones of the previous version
+        let diagnostics = errors
+            .chain(warnings)
+            .map(|(error, severity)| Diagnostic {
+                message: error.error.to_string(),
+                severity: Some(severity),
                 range: textrange_to_range(source.start(&error.token), source.end(&error.token)),
                 ..Default::default()
-            };
-
-            self.client
-                .publish_diagnostics(uri.clone(), vec![diagnostic], None)
-                .await
-        } else {
-            // Clear diagnostics
-            self.client
-                .publish_diagnostics(uri.clone(), vec![], None)
-                .await
-        }
+            })
+            .collect();
+
+        self.client
+            .publish_diagnostics(uri.clone(), diagnostics, None)
+            .await;
 
-        self.document_map.insert(uri, (content, expr));
+        self.document_map.insert(uri, (content, compilation));
     }
 }
 
@@ -1,11 +1,19 @@
 use std::path::PathBuf;
 
-use marble::{execute_string, source::Source};
+use marble::{execute_string_with_limits, limits::Limits, source::Source};
 use wasm_bindgen::prelude::*;
 
+/// Keeps programs, that never terminate, from freezing the page.
+/// There is no time limit, as the time isn't available on `wasm32-unknown-unknown`.
+const LIMITS: Limits = Limits {
+    steps: Some(100_000_000),
+    thunks: Some(10_000_000),
+    time: None,
+};
+
 #[wasm_bindgen]
 pub fn evaluate(s: &str) -> String {
-    match execute_string(s, PathBuf::default()) {
+    match execute_string_with_limits(s, PathBuf::default(), LIMITS) {
         Ok((value, output)) => format!("{output}{value}"),
         Err(e) => e.of_source(&Source::new(s)),
     }
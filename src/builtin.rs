@@ -1,10 +1,11 @@
 use std::sync::LazyLock;
 
+use crate::closure;
 use crate::environment::Environment;
-use crate::value::BuiltIn;
+use crate::value::{BuiltIn, List, Record};
 use crate::{
     call,
-    expr::Expr,
+    expr::{Expr, ExprRef},
     fun, fun_val, identifier,
     value::{Value, ValueRef},
 };
@@ -17,7 +18,7 @@ macro_rules! value {
 
 macro_rules! builtin {
     ($name: ident, $builtin: ident) => {
-        value!($name, ValueRef::new(Value::Builtin(BuiltIn::$builtin)));
+        value!($name, Value::Builtin(BuiltIn::$builtin).new_ref());
     };
 }
 
@@ -27,16 +28,84 @@ builtin!(ADD, Add);
 builtin!(SUB, Sub);
 builtin!(MUL, Mul);
 builtin!(DIV, Div);
+builtin!(MOD, Mod);
+builtin!(POW, Pow);
+builtin!(MIN, Min);
+builtin!(MAX, Max);
+builtin!(FLOOR, Floor);
+builtin!(CEIL, Ceil);
+builtin!(ROUND, Round);
+builtin!(ABS, Abs);
+builtin!(SQRT, Sqrt);
 builtin!(IMPORT, Import);
 
 builtin!(IS, Is);
 builtin!(ISNOT, IsNot);
+builtin!(LESS_THAN, LessThan);
+builtin!(GREATER_THAN, GreaterThan);
+
+builtin!(CONS, Cons);
+builtin!(HEAD, Head);
+builtin!(TAIL, Tail);
+builtin!(ISEMPTY, IsEmpty);
+builtin!(LENGTH, Length);
+builtin!(NTH, Nth);
+builtin!(APPEND, Append);
+
+builtin!(GET, Get);
+builtin!(HAS, Has);
+builtin!(SET, Set);
+builtin!(KEYS, Keys);
+
+builtin!(CONCAT, Concat);
+builtin!(STRING_LENGTH, StringLength);
+builtin!(SUBSTRING, Substring);
+builtin!(SPLIT, Split);
+builtin!(JOIN, Join);
+builtin!(TRIM, Trim);
+builtin!(CONTAINS, Contains);
+builtin!(STARTS_WITH, StartsWith);
+builtin!(UPPER, Upper);
+builtin!(LOWER, Lower);
+builtin!(CHAR_CODE, CharCode);
+builtin!(FROM_CHAR_CODE, FromCharCode);
+
+builtin!(TO_STRING, ToString);
+builtin!(TO_WORDS, ToWords);
+builtin!(PARSE_NUMBER, ParseNumber);
+builtin!(PARSE_WORDS, ParseWords);
+
+builtin!(READ_LINE, ReadLine);
+builtin!(READ_CHAR, ReadChar);
+builtin!(READ_ALL, ReadAll);
 
 value!(UNIT, Value::Unit.new_ref());
+value!(NIL, Value::List(List::Empty).new_ref());
+value!(EMPTY_RECORD, Value::Record(Record::new()).new_ref());
 
 value!(TRUE, fun_val!(fun!(identifier!(1))));
 value!(FALSE, fun_val!(fun!(identifier!(0))));
 
+/// Returns the church boolean of the value, see [`TRUE`] and [`FALSE`]
+pub fn boolean(value: bool) -> ValueRef {
+    if value { TRUE.clone() } else { FALSE.clone() }
+}
+
+// An option without a value, which calls the second function it is given
+value!(NONE, fun_val!(fun!(identifier!(0))));
+
+/// The body of an option with a value, which calls the first function it is given with the value
+static SOME: LazyLock<ExprRef> =
+    LazyLock::new(|| closure::convert(&fun!(call!(identifier!(1), identifier!(2))), 2));
+
+/// Calls the function in the environment with the value after it
+pub static APPLY: LazyLock<ExprRef> = LazyLock::new(|| call!(identifier!(1), identifier!(0)));
+
+/// Creates an option with the value, see [`SOME`]
+pub fn some(value: ValueRef) -> ValueRef {
+    Value::Fn(SOME.clone(), Environment::new(vec![value])).new_ref()
+}
+
 value!(
     NOT,
     fun_val!(fun!(fun!(call!(
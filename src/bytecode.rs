@@ -0,0 +1,115 @@
+//! A compact bytecode for marble expressions
+//!
+//! Expressions are compiled into a flat list of [`Op`]s, that are executed by a stack based
+//! virtual machine (see [`crate::vm`]). Every function body and every lazy call is compiled
+//! into its own [`Code`], which leaves exactly one fully unwrapped value on the stack.
+//!
+//! Expressions are compiled in one of two modes:
+//! - Strict: The result is needed right away, e.g. the callee of a call or the body of a function.
+//!   Calls are executed directly, without allocating a [`Value::LazyCall`] for them.
+//! - Lazy: The result may never be needed, e.g. the argument of a call.
+//!   Calls are wrapped in a [`Value::LazyCall`], that is only forced once its value is needed.
+//!
+//! Identifiers, values, globals and functions are cheap to evaluate, so they are never wrapped.
+//! Neither are [`Expr::Strict`] arguments, as they are compiled in strict mode.
+
+use std::sync::Arc;
+
+use crate::{
+    expr::{Expr, ExprRef},
+    token::Token,
+    value::ValueRef,
+};
+
+pub type CodeRef = Arc<Code>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    /// Pushes the value at the given slot of the environment
+    Load(usize),
+    /// Pushes the constant at the given index
+    Constant(usize),
+    /// Pushes a function, that captures its values from the environment, see [`crate::closure`]
+    Closure(usize),
+    /// Pushes a lazy call of the call at the given index, capturing the environment
+    Thunk(usize),
+    /// Forces the value on top of the stack
+    Force,
+    /// Calls the function below the top of the stack with the argument on top of the stack
+    Apply,
+    /// Returns the value on top of the stack from the current code
+    Return,
+}
+
+#[derive(Debug, Default)]
+pub struct Code {
+    pub ops: Vec<Op>,
+    /// The token of the expression each op was compiled from, used to annotate errors
+    pub tokens: Vec<Token>,
+    pub constants: Vec<ValueRef>,
+    /// Functions and calls, that closures and lazy calls are created from
+    pub exprs: Vec<ExprRef>,
+}
+
+impl Code {
+    /// Compiles an expression, so that executing the code returns its unwrapped value
+    pub fn compile(expr: &ExprRef) -> Code {
+        let mut code = Code::default();
+        code.strict(expr);
+        code.emit(Op::Return, expr.token);
+        code
+    }
+
+    fn strict(&mut self, expr: &ExprRef) {
+        match expr.expr() {
+            Expr::Call(lhs, rhs) => {
+                self.strict(lhs);
+                self.lazy(rhs);
+                self.emit(Op::Apply, lhs.token);
+            }
+            Expr::Identifier(_) => {
+                self.lazy(expr);
+                self.emit(Op::Force, expr.token);
+            }
+            Expr::Value(value) | Expr::Global(value) => {
+                self.lazy(expr);
+
+                if value.thunk().is_some() {
+                    self.emit(Op::Force, expr.token);
+                }
+            }
+            Expr::Fn(_, _) => self.lazy(expr),
+            Expr::Strict(inner) => self.strict(inner),
+        }
+    }
+
+    fn lazy(&mut self, expr: &ExprRef) {
+        let op = match expr.expr() {
+            Expr::Call(_, _) => Op::Thunk(self.add_expr(expr.clone())),
+            Expr::Identifier(ident) => Op::Load(*ident),
+            Expr::Value(value) | Expr::Global(value) => {
+                self.constants.push(value.clone());
+                Op::Constant(self.constants.len() - 1)
+            }
+            Expr::Fn(_, _) => Op::Closure(self.add_expr(expr.clone())),
+            Expr::Strict(inner) => return self.strict(inner),
+        };
+
+        self.emit(op, expr.token);
+    }
+
+    fn add_expr(&mut self, expr: ExprRef) -> usize {
+        self.exprs.push(expr);
+        self.exprs.len() - 1
+    }
+
+    fn emit(&mut self, op: Op, token: Token) {
+        self.ops.push(op);
+        self.tokens.push(token);
+    }
+
+    #[inline]
+    pub fn new_ref(self) -> CodeRef {
+        CodeRef::new(self)
+    }
+}
@@ -0,0 +1,159 @@
+//! Closure conversion over compiled expressions
+//!
+//! The compiler refers to variables by their de Bruijn index, the number of functions between
+//! the variable and the function binding it. Looking variables up that way walks a chain of
+//! environments, which every function would have to capture as a whole.
+//!
+//! This pass records the free variables of every [`Expr::Fn`] as its captures instead. A function
+//! copies just these values into a flat environment when it is created, and identifiers are
+//! rewritten to the slot of their value in the environment of the innermost function:
+//! - Slot zero is the argument of the function
+//! - Slot n + 1 is the n-th value captured by the function
+//!
+//! Lazy calls share the environment of the function they are created in,
+//! so they don't capture anything themselves.
+
+use std::collections::BTreeSet;
+
+use crate::{
+    expr::{Expr, ExprRef},
+    identifier::IdentRef,
+};
+
+/// Converts all functions in the expression to flat closures
+///
+/// The expression is evaluated in an environment of the given number of slots, where the
+/// variable with the de Bruijn index i is in slot i. This is the case for the root environment
+/// and for the environments the builtins create.
+pub fn convert(expr: &ExprRef, scope: usize) -> ExprRef {
+    let (expr, _) = record_captures(expr);
+    let slots: Vec<_> = (0..scope).collect();
+
+    assign_slots(&expr, &slots)
+}
+
+/// Records the free variables of every function as its captures
+///
+/// Returns the expression and its free variables. Both are still de Bruijn indices.
+fn record_captures(expr: &ExprRef) -> (ExprRef, BTreeSet<IdentRef>) {
+    match expr.expr() {
+        Expr::Identifier(ident) => (expr.clone(), BTreeSet::from([*ident])),
+        Expr::Value(_) | Expr::Global(_) => (expr.clone(), BTreeSet::new()),
+        Expr::Call(lhs, rhs) => {
+            let (lhs, mut free) = record_captures(lhs);
+            let (rhs, rhs_free) = record_captures(rhs);
+            free.extend(rhs_free);
+
+            (Expr::Call(lhs, rhs).annotate(expr.token), free)
+        }
+        Expr::Strict(inner) => {
+            let (inner, free) = record_captures(inner);
+            (Expr::Strict(inner).annotate(expr.token), free)
+        }
+        Expr::Fn(body, _) => {
+            let (body, free) = record_captures(body);
+
+            // The argument is bound by the function, every other variable is captured
+            let captures: Vec<_> = free
+                .into_iter()
+                .filter_map(|ident| ident.checked_sub(1))
+                .collect();
+            let free = captures.iter().copied().collect();
+
+            (Expr::Fn(body, captures).annotate(expr.token), free)
+        }
+    }
+}
+
+/// Rewrites the identifiers and captures from de Bruijn indices to slots
+///
+/// The variable with the de Bruijn index i is in the slot ```slots[i]```
+fn assign_slots(expr: &ExprRef, slots: &[usize]) -> ExprRef {
+    match expr.expr() {
+        Expr::Identifier(ident) => Expr::Identifier(slots[*ident]).annotate(expr.token),
+        Expr::Value(_) | Expr::Global(_) => expr.clone(),
+        Expr::Call(lhs, rhs) => {
+            Expr::Call(assign_slots(lhs, slots), assign_slots(rhs, slots)).annotate(expr.token)
+        }
+        Expr::Strict(inner) => Expr::Strict(assign_slots(inner, slots)).annotate(expr.token),
+        Expr::Fn(body, captures) => {
+            // Variables, that aren't captured, are never used in the body, so their slot doesn't matter
+            let mut body_slots = vec![0; captures.last().map_or(1, |last| last + 2)];
+
+            for (idx, ident) in captures.iter().enumerate() {
+                body_slots[ident + 1] = idx + 1;
+            }
+
+            let captures = captures.iter().map(|ident| slots[*ident]).collect();
+
+            Expr::Fn(assign_slots(body, &body_slots), captures).annotate(expr.token)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        compiler::Compiler,
+        expr::{Expr, ExprRef},
+        scanner::Scanner,
+        source::Source,
+    };
+
+    /// The compiler converts the closures of every expression it compiles
+    fn converted(code: &str) -> ExprRef {
+        let source = Source::new(code);
+        Compiler::new(&source, Scanner::new(&source))
+            .compile()
+            .unwrap()
+    }
+
+    /// Returns the captures of all functions, the outermost function first
+    fn captures(expr: &ExprRef) -> Vec<Vec<usize>> {
+        match expr.expr() {
+            Expr::Call(lhs, rhs) => [captures(lhs), captures(rhs)].concat(),
+            Expr::Fn(body, slots) => [vec![slots.clone()], captures(body)].concat(),
+            Expr::Strict(inner) => captures(inner),
+            Expr::Identifier(_) | Expr::Value(_) | Expr::Global(_) => Vec::new(),
+        }
+    }
+
+    #[test]
+    pub fn only_free_variables() {
+        let expr = converted("let X be One in let Y be Two in fn Z do X end");
+
+        assert_eq!(captures(&expr), [vec![], vec![0], vec![1]]);
+    }
+
+    #[test]
+    pub fn identifier_slots() {
+        let expr = converted("let X be One in let Y be Two in fn Z do X of Z of Y end");
+
+        let Expr::Call(x, _) = expr.expr() else {
+            panic!("Let is a call")
+        };
+        let Expr::Fn(y, _) = x.expr() else {
+            panic!("Let calls a function")
+        };
+        let Expr::Call(y, _) = y.expr() else {
+            panic!("Let is a call")
+        };
+        let Expr::Fn(body, _) = y.expr() else {
+            panic!("Let calls a function")
+        };
+        let Expr::Fn(body, slots) = body.expr() else {
+            panic!("Body is a function")
+        };
+
+        // The function captures Y and X, which are in slot zero and one of the surrounding let
+        assert_eq!(slots, &[0, 1]);
+        assert!(matches!(
+            body.expr(),
+            Expr::Call(lhs, y) if matches!(
+                (lhs.expr(), y.expr()),
+                (Expr::Call(x, z), Expr::Identifier(1))
+                    if matches!((x.expr(), z.expr()), (Expr::Identifier(2), Expr::Identifier(0)))
+            )
+        ));
+    }
+}
@@ -1,12 +1,17 @@
-use std::iter::Peekable;
+use std::{collections::HashMap, iter::Peekable};
+
+use line_index::TextRange;
 
 use crate::{
-    builtin,
+    builtin, closure,
     error::{AnnotatedError, Error},
     expr::{Expr, ExprRef},
     identifier::IdentifierTable,
+    number::Number,
+    optimizer,
     scanner::Scanner,
     source::Source,
+    strictness,
     token::{Token, TokenType},
     value::{Value, ValueRef},
 };
@@ -15,11 +20,30 @@ type ExprResult = Result<ExprRef, AnnotatedError>;
 
 type Binding<'a> = (&'a str, ValueRef);
 
+/// The result of compiling a source, that may contain errors
+#[derive(Debug)]
+pub struct Compilation {
+    /// The compiled expression, in which every erroneous part is replaced by `Unit`
+    pub expr: ExprRef,
+    pub errors: Vec<AnnotatedError>,
+    pub warnings: Vec<AnnotatedError>,
+}
+
 pub struct Compiler<'a> {
     source: &'a Source<'a>,
     scanner: Peekable<Scanner<'a>>,
     identifiers: IdentifierTable<'a>,
-    extra_bindings: Vec<Binding<'a>>,
+    /// The values of the global bindings, which are shadowed by any local binding of their name
+    globals: HashMap<&'a str, ValueRef>,
+    errors: Vec<AnnotatedError>,
+    warnings: Vec<AnnotatedError>,
+    /// Whether unterminated strings and comments are only reported as warnings
+    allow_unterminated: bool,
+    /// Whether the compiled expression is optimized, see [`crate::optimizer`]
+    optimize: bool,
+    /// Set after an error, until the compiler reaches a point where compiling can continue.
+    /// Errors in between are most likely caused by the first one, so they aren't reported
+    recovering: bool,
 }
 
 impl<'a> Compiler<'a> {
@@ -36,11 +60,54 @@ impl<'a> Compiler<'a> {
             ("Print", builtin::PRINT.clone()),
             ("Is", builtin::IS.clone()),
             ("IsNot", builtin::ISNOT.clone()),
+            ("LessThan", builtin::LESS_THAN.clone()),
+            ("GreaterThan", builtin::GREATER_THAN.clone()),
             ("Add", builtin::ADD.clone()),
             ("Sub", builtin::SUB.clone()),
             ("Mul", builtin::MUL.clone()),
             ("Div", builtin::DIV.clone()),
+            ("Mod", builtin::MOD.clone()),
+            ("Pow", builtin::POW.clone()),
+            ("Min", builtin::MIN.clone()),
+            ("Max", builtin::MAX.clone()),
+            ("Floor", builtin::FLOOR.clone()),
+            ("Ceil", builtin::CEIL.clone()),
+            ("Round", builtin::ROUND.clone()),
+            ("Abs", builtin::ABS.clone()),
+            ("Sqrt", builtin::SQRT.clone()),
             ("Import", builtin::IMPORT.clone()),
+            ("Nil", builtin::NIL.clone()),
+            ("Cons", builtin::CONS.clone()),
+            ("Head", builtin::HEAD.clone()),
+            ("Tail", builtin::TAIL.clone()),
+            ("IsEmpty", builtin::ISEMPTY.clone()),
+            ("Length", builtin::LENGTH.clone()),
+            ("Nth", builtin::NTH.clone()),
+            ("Append", builtin::APPEND.clone()),
+            ("EmptyRecord", builtin::EMPTY_RECORD.clone()),
+            ("Get", builtin::GET.clone()),
+            ("Has", builtin::HAS.clone()),
+            ("Set", builtin::SET.clone()),
+            ("Keys", builtin::KEYS.clone()),
+            ("Concat", builtin::CONCAT.clone()),
+            ("StringLength", builtin::STRING_LENGTH.clone()),
+            ("Substring", builtin::SUBSTRING.clone()),
+            ("Split", builtin::SPLIT.clone()),
+            ("Join", builtin::JOIN.clone()),
+            ("Trim", builtin::TRIM.clone()),
+            ("Contains", builtin::CONTAINS.clone()),
+            ("StartsWith", builtin::STARTS_WITH.clone()),
+            ("Upper", builtin::UPPER.clone()),
+            ("Lower", builtin::LOWER.clone()),
+            ("CharCode", builtin::CHAR_CODE.clone()),
+            ("FromCharCode", builtin::FROM_CHAR_CODE.clone()),
+            ("ToString", builtin::TO_STRING.clone()),
+            ("ToWords", builtin::TO_WORDS.clone()),
+            ("ParseNumber", builtin::PARSE_NUMBER.clone()),
+            ("ParseWords", builtin::PARSE_WORDS.clone()),
+            ("ReadLine", builtin::READ_LINE.clone()),
+            ("ReadChar", builtin::READ_CHAR.clone()),
+            ("ReadAll", builtin::READ_ALL.clone()),
         ]
     }
 
@@ -49,74 +116,155 @@ impl<'a> Compiler<'a> {
             source,
             scanner: scanner.peekable(),
             identifiers: IdentifierTable::new(),
-            extra_bindings: Vec::new(),
+            globals: HashMap::new(),
+            errors: Vec::new(),
+            warnings: Vec::new(),
+            allow_unterminated: false,
+            optimize: false,
+            recovering: false,
         }
     }
 
+    /// Binds the values to their names globally, replacing earlier global bindings of the same name
     pub fn with_bindings(&mut self, bindings: Vec<Binding<'a>>) -> &Self {
-        self.extra_bindings.extend(bindings);
+        self.globals.extend(bindings);
 
         self
     }
 
-    pub fn compile(mut self) -> ExprResult {
-        for (ident, _) in &self.extra_bindings {
-            self.identifiers.push(ident);
+    /// Reports unterminated strings and comments as warnings instead of errors.
+    /// They then extend to the end of the source.
+    pub fn with_unterminated_allowed(&mut self, allow: bool) -> &Self {
+        self.allow_unterminated = allow;
+
+        self
+    }
+
+    /// Optimizes the compiled expression, see [`crate::optimizer`]
+    pub fn with_optimization(&mut self, optimize: bool) -> &Self {
+        self.optimize = optimize;
+
+        self
+    }
+
+    /// Compiles the source, failing with the first error
+    pub fn compile(self) -> ExprResult {
+        let compilation = self.compile_all();
+
+        match compilation.errors.into_iter().next() {
+            Some(error) => Err(error),
+            None => Ok(compilation.expr),
         }
+    }
+
+    /// Compiles the source, returning all errors and warnings alongside the partial expression
+    ///
+    /// After an error the compiler skips ahead to the next `end`, `in`, `then` or the end of the
+    /// file and continues from there. The skipped parts are replaced by `Unit` in the expression.
+    ///
+    /// The strict arguments of the expression are marked and its functions are converted to
+    /// flat closures, so it can be interpreted right away.
+    pub fn compile_all(mut self) -> Compilation {
+        let expr = self.expression();
+
+        let token = *self.peek();
+
+        if token.token_type != TokenType::Eof {
+            self.report(Error::ExpectedEofAfterExpression.annotate(token));
+
+            // The rest of the file is still compiled, to find the errors in it
+            while self.matches(TokenType::Eof).is_none() {
+                self.consume();
 
-        let mut expr = self.expression()?;
+                if self.peek().token_type != TokenType::Eof {
+                    self.recovering = false;
+                    self.expression();
+                }
+            }
+        }
 
-        for (_, provider) in self.extra_bindings.iter().rev() {
-            let function = Expr::Fn(expr).default_ref();
-            expr = Expr::Call(function, Expr::Value(provider.clone()).default_ref()).default_ref();
+        Compilation {
+            expr: self.finish(expr),
+            errors: self.errors,
+            warnings: self.warnings,
         }
+    }
 
-        self.match_consume(TokenType::Eof, Error::ExpectedEofAfterExpression)?;
+    /// Runs the passes over the expression, that prepare it for the interpreter
+    ///
+    /// The passes work on de Bruijn indices, so the closures are converted last.
+    fn finish(&self, mut expr: ExprRef) -> ExprRef {
+        if self.optimize {
+            expr = optimizer::optimize(&expr);
+        }
 
-        Ok(expr)
+        closure::convert(&strictness::analyse(&expr), 0)
     }
 
-    fn expression(&mut self) -> ExprResult {
+    fn expression(&mut self) -> ExprRef {
         self.then_expression()
     }
 
     fn let_expression(&mut self) -> ExprResult {
-        if self.matches(TokenType::Let).is_none() {
+        let Some(let_token) = self.matches(TokenType::Let) else {
             return self.call();
-        }
-
-        let variable_name = self.try_identifier(Error::ExpectedIdentifierAsVariableName)?;
+        };
+
+        let variable_name = match self.try_identifier(Error::ExpectedIdentifierAsVariableName) {
+            Ok(variable_name) => variable_name,
+            Err(error) => {
+                self.recover(error);
+                // Like 'then', 'let' is a keyword, so it never shadows an identifier
+                "let"
+            }
+        };
 
-        let be_token = self.match_consume(TokenType::Be, Error::ExpectedBeInAssignment)?;
+        let be_token = self.expect(TokenType::Be, Error::ExpectedBeInAssignment(let_token));
 
-        let value = self.then_expression()?;
+        let value = match be_token {
+            Some(_) => self.then_expression(),
+            None => Self::placeholder(*self.peek()),
+        };
 
-        let in_token = self.match_consume(TokenType::In, Error::ExpectedInAfterAssignment)?;
+        let Some(in_token) =
+            self.expect(TokenType::In, Error::ExpectedInAfterAssignment(let_token))
+        else {
+            return Ok(value);
+        };
 
         // After the initiliazer is finished, the identifier is pushed, so that it isnt available in the initializer
         self.identifiers.push(variable_name);
 
-        let body = self.then_expression()?;
+        let body = self.then_expression();
 
         self.identifiers.pop();
 
-        let function = Expr::Fn(body).annotate(in_token);
+        let function = Expr::Fn(body, Vec::new()).annotate(in_token);
 
-        Ok(Expr::Call(function, value).annotate(be_token))
+        Ok(Expr::Call(function, value).annotate(be_token.unwrap_or(in_token)))
     }
 
-    fn then_expression(&mut self) -> ExprResult {
-        let mut lhs = self.let_expression()?;
+    fn then_expression(&mut self) -> ExprRef {
+        let mut lhs = self.operand();
 
         while let Some(then_token) = self.matches(TokenType::Then) {
+            self.recovering = false;
+
             // The value here is discarded, then as a variable is never accessable as it is a keyword
             self.identifiers.push("then");
-            let rhs = self.let_expression()?;
+            let rhs = self.operand();
             self.identifiers.pop();
-            lhs = Expr::Call(lhs, Expr::Fn(rhs).annotate(then_token)).annotate(then_token);
+            lhs = Expr::Call(lhs, Expr::Fn(rhs, Vec::new()).annotate(then_token))
+                .annotate(then_token);
         }
 
-        Ok(lhs)
+        lhs
+    }
+
+    /// Compiles one side of a 'then', recovering from any error in it
+    fn operand(&mut self) -> ExprRef {
+        self.let_expression()
+            .unwrap_or_else(|error| self.recover(error))
     }
 
     fn call(&mut self) -> ExprResult {
@@ -131,9 +279,24 @@ impl<'a> Compiler<'a> {
     }
 
     fn value(&mut self) -> ExprResult {
-        let token = self.consume();
+        let token = *self.peek();
+
+        // Tokens, that can't start an expression, are left in place to recover at them
+        if !matches!(
+            token.token_type,
+            TokenType::Do
+                | TokenType::Fn
+                | TokenType::String(_)
+                | TokenType::Number
+                | TokenType::Identifier
+        ) {
+            return Err(Error::ExpectedExpressionFound(token).annotate(token));
+        }
+
+        self.consume();
+
         match token.token_type {
-            TokenType::Do => self.block(),
+            TokenType::Do => Ok(self.block(token)),
             TokenType::Fn => self.function(token),
             TokenType::String(is_terminated) => {
                 let lexeme = self.source.lexeme(&token);
@@ -141,16 +304,44 @@ impl<'a> Compiler<'a> {
                     "string" => Ok(self.string_of("", token)),
                     _ if is_terminated && lexeme.len() == 7 => Ok(self.string_of("", token)),
                     _ if is_terminated => Ok(self.string_of(&lexeme[4..lexeme.len() - 4], token)),
-                    _ => Ok(self.string_of(&lexeme[4..], token)),
+                    _ => {
+                        // The error points at the opening 'str' instead of the rest of the source
+                        let start = token.range.start();
+                        let opening = Token {
+                            range: TextRange::new(start, (u32::from(start) + 3).into()),
+                            ..token
+                        };
+                        self.unterminated(Error::UnterminatedString.annotate(opening));
+
+                        Ok(self.string_of(lexeme.get(4..).unwrap_or_default(), token))
+                    }
+                }
+            }
+            TokenType::Number => {
+                let lexeme = self.source.lexeme(&token);
+                let number = Number::from_words(lexeme)
+                    .or_else(|| Number::from_digits(lexeme))
+                    .expect("Number tokens are valid numbers");
+
+                Ok(Expr::Value(ValueRef::Number(number)).annotate(token))
+            }
+            TokenType::Identifier => {
+                let name = self.source.lexeme(&token);
+
+                match self.identifiers.distance_from_top(name) {
+                    Ok(ident) => Ok(Expr::Identifier(ident).annotate(token)),
+                    Err(_) if let Some(value) = self.globals.get(name) => {
+                        Ok(Expr::Global(value.clone()).annotate(token))
+                    }
+                    // An unknown identifier doesn't break the structure of the code,
+                    // so compiling continues right after it
+                    Err(error) => {
+                        self.report(error.annotate(token));
+                        Ok(Self::placeholder(token))
+                    }
                 }
             }
-            TokenType::Number(num) => Ok(Expr::Value(Value::Number(num).new_ref()).annotate(token)),
-            TokenType::Identifier => self
-                .identifiers
-                .distance_from_top(self.source.lexeme(&token))
-                .map(|ident| Expr::Identifier(ident).annotate(token))
-                .map_err(|e| e.annotate(token)),
-            _ => Err(Error::ExpectedExpressionFound(token).annotate(token)),
+            _ => unreachable!("Only tokens, that start an expression, are consumed"),
         }
     }
 
@@ -158,10 +349,10 @@ impl<'a> Compiler<'a> {
         Expr::Value(Value::String(string.to_string()).new_ref()).annotate(token)
     }
 
-    fn block(&mut self) -> ExprResult {
-        let expr = self.expression()?;
-        self.match_consume(TokenType::End, Error::ExpectedEndAfterDoBlock)?;
-        Ok(expr)
+    fn block(&mut self, do_token: Token) -> ExprRef {
+        let expr = self.expression();
+        self.expect(TokenType::End, Error::ExpectedEndAfterDoBlock(do_token));
+        expr
     }
 
     fn function(&mut self, fn_token: Token) -> ExprResult {
@@ -175,15 +366,18 @@ impl<'a> Compiler<'a> {
             self.identifiers.push(identifier);
         }
 
-        self.match_consume(TokenType::Do, Error::ExpectedDoAsFunctionBody)?;
+        let body = self
+            .match_consume(TokenType::Do, Error::ExpectedDoAsFunctionBody(fn_token))
+            .map(|do_token| self.block(do_token));
 
-        let body = self.block()?;
+        for _ in &arguments {
+            self.identifiers.pop();
+        }
 
-        let mut expr = body;
+        let mut expr = body?;
 
-        for _ in 0..arguments.len() {
-            expr = Expr::Fn(expr).annotate(fn_token);
-            self.identifiers.pop();
+        for _ in &arguments {
+            expr = Expr::Fn(expr, Vec::new()).annotate(fn_token);
         }
 
         Ok(expr)
@@ -196,10 +390,19 @@ impl<'a> Compiler<'a> {
     }
 
     fn peek(&mut self) -> &Token {
+        // The scanner only returns comments, if they are unterminated
+        while let Some(comment) = self
+            .scanner
+            .next_if(|token| matches!(token.token_type, TokenType::Comment(_)))
+        {
+            self.unterminated(Error::UnterminatedComment.annotate(comment));
+        }
+
         self.scanner.peek().unwrap()
     }
 
     fn consume(&mut self) -> Token {
+        self.peek();
         self.scanner.next().unwrap()
     }
 
@@ -215,4 +418,67 @@ impl<'a> Compiler<'a> {
         let error = error.annotate(*self.peek());
         self.matches(token).ok_or(error)
     }
+
+    /// Consumes the token, recovering if it is missing. This may still find the token
+    /// further ahead, e.g. the 'in' of a let with a broken value
+    fn expect(&mut self, token: TokenType, error: Error) -> Option<Token> {
+        let found = match self.match_consume(token, error) {
+            Ok(found) => Some(found),
+            Err(error) => {
+                self.recover(error);
+                self.matches(token)
+            }
+        };
+
+        if found.is_some() {
+            self.recovering = false;
+        }
+
+        found
+    }
+
+    /// Records an unterminated string or comment, which doesn't affect the structure of the code
+    fn unterminated(&mut self, error: AnnotatedError) {
+        if self.allow_unterminated {
+            self.warnings.push(error);
+        } else {
+            self.report(error);
+        }
+    }
+
+    /// Records the error, unless it is caused by an earlier error
+    fn report(&mut self, error: AnnotatedError) {
+        if !self.recovering {
+            self.errors.push(error);
+        }
+    }
+
+    /// Records the error and skips to the next 'end', 'in' or 'then', that isn't nested
+    /// in a do block or a let, or to the end of the file. Returns a placeholder for the skipped code
+    fn recover(&mut self, error: AnnotatedError) -> ExprRef {
+        let token = error.token;
+
+        self.report(error);
+        self.recovering = true;
+
+        let mut depth = 0;
+
+        loop {
+            match self.peek().token_type {
+                TokenType::Eof => break,
+                TokenType::End | TokenType::In | TokenType::Then if depth == 0 => break,
+                TokenType::Do | TokenType::Let => depth += 1,
+                TokenType::End | TokenType::In => depth -= 1,
+                _ => {}
+            }
+
+            self.consume();
+        }
+
+        Self::placeholder(token)
+    }
+
+    fn placeholder(token: Token) -> ExprRef {
+        Expr::Value(builtin::UNIT.clone()).annotate(token)
+    }
 }
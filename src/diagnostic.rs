@@ -0,0 +1,270 @@
+//! Renders errors together with the source code they point to
+//!
+//! The offending token is underlined with carets, related tokens, like the 'do' of a block that
+//! is never closed, are underlined with dashes and labeled. Hints and the stack trace of
+//! runtime errors are appended as notes:
+//!
+//! ```text
+//! Compile Error: Expected 'end' after do block. Did you miss a function call ('of')?
+//!  --> 3:1
+//!   |
+//! 1 | let Double be fn X do
+//!   |                    -- This 'do' block is never closed
+//! ...
+//! 3 | in Double of Two
+//!   | ^^
+//!   = hint: Every 'do' needs a matching 'end', including the 'do' of a function body.
+//! ```
+
+use std::fmt::Write;
+
+use crate::{
+    error::AnnotatedError,
+    source::Source,
+    token::{Token, TokenType},
+};
+
+const RED: &str = "\x1b[1;31m";
+const BLUE: &str = "\x1b[1;34m";
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Renderer {
+    colour: bool,
+}
+
+struct Label {
+    token: Token,
+    message: &'static str,
+    primary: bool,
+}
+
+impl Renderer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether the output should be coloured with ANSI escape codes
+    pub fn with_colour(&mut self, colour: bool) -> &Self {
+        self.colour = colour;
+
+        self
+    }
+
+    pub fn render(&self, error: &AnnotatedError, source: &Source) -> String {
+        let trace = error.trace_locations(source);
+
+        // Runtime errors may point into an imported file
+        let file = error.file_of(source, &error.token);
+        let imported = file.map(|file| Source::in_file(&file.code, error.token.file));
+        let source = imported.as_ref().unwrap_or(source);
+
+        let mut labels = vec![Label {
+            token: error.token,
+            message: "",
+            primary: true,
+        }];
+
+        labels.extend(
+            error
+                .error
+                .labels()
+                .into_iter()
+                .filter(|(token, _)| token.file == error.token.file)
+                .map(|(token, message)| Label {
+                    token,
+                    message,
+                    primary: false,
+                }),
+        );
+
+        labels.sort_by_key(|label| label.token.range.start());
+
+        let last_line = labels
+            .iter()
+            .map(|label| source.start(&label.token).line + 1)
+            .max()
+            .unwrap_or(1);
+        let pad = " ".repeat(last_line.to_string().len());
+
+        let mut output = format!(
+            "{}{}{}{}: {}{}\n",
+            self.paint(RED),
+            error.error.error_type(),
+            self.paint(RESET),
+            self.paint(BOLD),
+            error.error,
+            self.paint(RESET)
+        );
+
+        // Generated tokens don't point to any code, so there is nothing to show
+        if error.token.token_type != TokenType::Generated {
+            let start = source.start(&error.token);
+            let name = file
+                .map(|file| format!("{}:", file.name))
+                .unwrap_or_default();
+
+            let _ = writeln!(
+                output,
+                "{pad}{}-->{} {name}{}:{}",
+                self.paint(BLUE),
+                self.paint(RESET),
+                start.line + 1,
+                start.col + 1
+            );
+            let _ = writeln!(output, "{pad} {}|{}", self.paint(BLUE), self.paint(RESET));
+
+            self.snippet(&mut output, source, &labels, &pad);
+        }
+
+        if let Some(hint) = error.error.hint() {
+            let _ = writeln!(
+                output,
+                "{pad} {}= hint:{} {hint}",
+                self.paint(BLUE),
+                self.paint(RESET)
+            );
+        }
+
+        if !trace.is_empty() {
+            let _ = writeln!(
+                output,
+                "{pad} {}= stack trace:{}",
+                self.paint(BLUE),
+                self.paint(RESET)
+            );
+
+            for location in trace {
+                let _ = writeln!(output, "{pad}     at {location}");
+            }
+        }
+
+        output
+    }
+
+    /// Writes every line with a label, followed by the underlined labels
+    fn snippet(&self, output: &mut String, source: &Source, labels: &[Label], pad: &str) {
+        let mut previous_line = None;
+
+        for label in labels {
+            let start = source.start(&label.token);
+            let line = line_text(source, start.line);
+
+            if previous_line != Some(start.line) {
+                if previous_line.is_some_and(|previous| previous + 1 < start.line) {
+                    let _ = writeln!(output, "{}...{}", self.paint(BLUE), self.paint(RESET));
+                }
+
+                let _ = writeln!(
+                    output,
+                    "{}{:>width$} |{} {line}",
+                    self.paint(BLUE),
+                    start.line + 1,
+                    self.paint(RESET),
+                    width = pad.len()
+                );
+            }
+
+            previous_line = Some(start.line);
+
+            let end = source.end(&label.token);
+            let col = start.col as usize;
+            let end_col = if end.line == start.line {
+                end.col as usize
+            } else {
+                line.len()
+            };
+
+            // Tabs are kept, so that the markers line up with the text above
+            let indent: String = line
+                .get(..col)
+                .unwrap_or(line)
+                .chars()
+                .map(|c| if c == '\t' { '\t' } else { ' ' })
+                .collect();
+            let width = line
+                .get(col..end_col)
+                .map_or(0, |text| text.chars().count());
+
+            let (marker, colour) = if label.primary {
+                ('^', RED)
+            } else {
+                ('-', BLUE)
+            };
+            let markers = marker.to_string().repeat(width.max(1));
+
+            let _ = writeln!(
+                output,
+                "{pad} {}|{} {indent}{}{}{}{}",
+                self.paint(BLUE),
+                self.paint(RESET),
+                self.paint(colour),
+                markers,
+                if label.message.is_empty() {
+                    String::new()
+                } else {
+                    format!(" {}", label.message)
+                },
+                self.paint(RESET)
+            );
+        }
+    }
+
+    fn paint(&self, code: &'static str) -> &'static str {
+        if self.colour { code } else { "" }
+    }
+}
+
+/// Returns the text of the line, without its line break
+fn line_text<'a>(source: &Source<'a>, line: u32) -> &'a str {
+    source
+        .str
+        .split('\n')
+        .nth(line as usize)
+        .unwrap_or_default()
+        .trim_end_matches('\r')
+}
+
+#[cfg(test)]
+mod test {
+    use super::Renderer;
+    use crate::{compile, source::Source};
+
+    fn render(code: &str) -> String {
+        let source = Source::new(code);
+        let error = compile(&source).unwrap_err();
+        Renderer::new().render(&error, &source)
+    }
+
+    #[test]
+    pub fn unclosed_block() {
+        assert_eq!(
+            render("let Double be fn X do\n    Add of X of X\nin Double of Two"),
+            "Compile Error: Expected 'end' after do block. Did you miss a function call ('of')?
+ --> 3:1
+  |
+1 | let Double be fn X do
+  |                    -- This 'do' block is never closed
+...
+3 | in Double of Two
+  | ^^
+  = hint: Every 'do' needs a matching 'end', including the 'do' of a function body.
+"
+        );
+    }
+
+    #[test]
+    pub fn undefined_identifier() {
+        assert_eq!(
+            render("Add of\tOne of Tow"),
+            "Compile Error: Identifier Tow is not defined.
+ --> 1:15
+  |
+1 | Add of\tOne of Tow
+  |       \t       ^^^
+  = hint: Bindings are only visible after their 'in', function arguments only in the function body.
+"
+        );
+    }
+}
@@ -4,28 +4,36 @@ use crate::value::ValueRef;
 
 pub type EnvRef = Arc<Environment>;
 
+/// The values an expression can access, addressed by their slot
+///
+/// A function only captures the values it uses (see [`crate::closure`]), so looking up
+/// a value never walks more than the environment of the call and the captures below it.
 #[derive(Clone, Debug)]
 pub enum Environment {
-    Value { value: ValueRef, parent: EnvRef },
-    Root,
+    /// The values a function captured, when it was created
+    Captures(Vec<ValueRef>),
+    /// The argument of a call in slot zero, followed by the values the called function captured
+    Call {
+        argument: ValueRef,
+        captures: EnvRef,
+    },
 }
 
 impl Environment {
     #[allow(clippy::declare_interior_mutable_const)]
-    pub const ROOT: LazyLock<EnvRef> = LazyLock::new(|| EnvRef::new(Environment::Root));
+    pub const ROOT: LazyLock<EnvRef> =
+        LazyLock::new(|| EnvRef::new(Environment::Captures(Vec::new())));
 
-    pub fn extend(environment: EnvRef, value: ValueRef) -> EnvRef {
-        EnvRef::new(Environment::Value {
-            value,
-            parent: EnvRef::clone(&environment),
-        })
+    pub fn new(values: Vec<ValueRef>) -> EnvRef {
+        EnvRef::new(Environment::Captures(values))
     }
 
-    pub fn pop(environment: &EnvRef) -> EnvRef {
-        match EnvRef::as_ref(environment) {
-            Environment::Value { value: _, parent } => EnvRef::clone(parent),
-            Environment::Root => panic!("Popped the root Environment"),
-        }
+    /// Creates the environment of a call to a function, that captured the environment
+    pub fn extend(environment: EnvRef, value: ValueRef) -> EnvRef {
+        EnvRef::new(Environment::Call {
+            argument: value,
+            captures: environment,
+        })
     }
 
     pub fn root() -> EnvRef {
@@ -33,16 +41,21 @@ impl Environment {
         Self::ROOT.clone()
     }
 
-    pub fn find(&self, depth: usize) -> ValueRef {
+    pub fn find(&self, slot: usize) -> ValueRef {
         match self {
-            Self::Root => panic!("Tried to get on environment root"),
-            Self::Value { value, parent } => {
-                if depth == 0 {
-                    ValueRef::clone(value)
+            Self::Captures(values) => ValueRef::clone(&values[slot]),
+            Self::Call { argument, captures } => {
+                if slot == 0 {
+                    ValueRef::clone(argument)
                 } else {
-                    parent.find(depth - 1)
+                    captures.find(slot - 1)
                 }
             }
         }
     }
+
+    /// Creates the environment of a function, that captures the values in the slots
+    pub fn capture(&self, slots: &[usize]) -> EnvRef {
+        Self::new(slots.iter().map(|slot| self.find(*slot)).collect())
+    }
 }
@@ -1,31 +1,70 @@
-use std::fmt::{Debug, Display};
+use std::{
+    collections::HashMap,
+    fmt::{Debug, Display, Write},
+    time::Duration,
+};
 
-use crate::{source::Source, token::Token, value::ValueRef};
+use crate::{
+    number::Number,
+    source::{FileId, FileRef, Source},
+    token::{Token, TokenType},
+    value::ValueRef,
+};
+
+/// Deeply recursive programs have huge stack traces, so only the innermost and
+/// outermost calls are shown
+const TRACE_FRAMES_SHOWN: usize = 8;
 
 #[derive(Debug, Clone)]
 pub struct AnnotatedError {
     pub error: Error,
     pub token: Token,
+    pub trace: Box<StackTrace>,
+}
+
+/// Where a runtime error happened, beyond the token it is annotated with
+#[derive(Debug, Clone, Default)]
+pub struct StackTrace {
+    /// The calls waiting for the result of the failed evaluation, the innermost call first
+    pub calls: Vec<Token>,
+    /// The imported files of the run, that the tokens may point into
+    pub files: Vec<FileRef>,
 }
 
 #[derive(Clone)]
 pub enum Error {
     ExpectedIdentifierAsVariableName,
     ExpectedIdentifierAsFunctionArgument,
-    ExpectedBeInAssignment,
-    ExpectedInAfterAssignment,
+    ExpectedBeInAssignment(Token),
+    ExpectedInAfterAssignment(Token),
     ExpectedEofAfterExpression,
     ExpectedExpressionFound(Token),
-    ExpectedEndAfterDoBlock,
-    ExpectedDoAsFunctionBody,
+    ExpectedEndAfterDoBlock(Token),
+    ExpectedDoAsFunctionBody(Token),
+    UnterminatedString,
+    UnterminatedComment,
     ValueNotCallable(ValueRef),
     IdentifierIsNotDefined(String),
     ArgumentToOperatorMustBeANumber(&'static str),
+    ArgumentToOperatorMustBeAList(&'static str),
+    ArgumentToOperatorMustBeAString(&'static str),
+    ArgumentToOperatorMustBeARecord(&'static str),
+    ListIsEmpty(&'static str),
+    StringIsEmpty(&'static str),
+    InvalidCharCode(Number),
+    NumberHasNoWords(Number),
+    KeyNotInRecord(String),
+    IndexOutOfBounds(Number, usize),
     ArgumentToImportMustBeAString,
     ImportCouldNotBeResolved(String),
     ErrorInImportedFile(String, String),
+    CyclicImport(Vec<String>),
     ValueDependsOnItself,
     OutputNotWritable,
+    InputNotReadable,
+    StepLimitExceeded(u64),
+    ThunkLimitExceeded(usize),
+    TimeLimitExceeded(Duration),
 }
 
 pub enum ErrorType {
@@ -44,18 +83,124 @@ impl Display for ErrorType {
 
 impl AnnotatedError {
     pub fn new(error: Error, token: Token) -> AnnotatedError {
-        AnnotatedError { error, token }
+        AnnotatedError {
+            error,
+            token,
+            trace: Box::default(),
+        }
+    }
+
+    pub fn with_trace(mut self, calls: Vec<Token>) -> Self {
+        // Generated tokens, like the ones of the builtins, don't point to any code
+        self.trace.calls = calls
+            .into_iter()
+            .filter(|token| token.token_type != TokenType::Generated)
+            .collect();
+
+        self
+    }
+
+    pub fn with_files(mut self, files: Vec<FileRef>) -> Self {
+        self.trace.files = files;
+
+        self
     }
 
     pub fn of_source(&self, source: &Source) -> String {
-        let line_col = source.start(&self.token);
-        format!(
-            "{} at {}:{} => '{}'\n{}",
+        let mut sources = HashMap::new();
+
+        let mut message = format!(
+            "{} at {}\n{}",
             self.error.error_type(),
+            self.location(source, &mut sources, &self.token),
+            self.error
+        );
+
+        let trace = self.trace_locations(source);
+
+        if !trace.is_empty() {
+            message.push_str("\nStack trace:");
+
+            for location in trace {
+                let _ = write!(message, "\n    at {location}");
+            }
+        }
+
+        message
+    }
+
+    /// Formats the locations of the calls in the stack trace, the innermost call first
+    pub fn trace_locations(&self, source: &Source) -> Vec<String> {
+        let mut sources = HashMap::new();
+
+        // Recursive calls from the same place are only shown once
+        let mut calls: Vec<(Token, usize)> = Vec::new();
+
+        for token in &self.trace.calls {
+            match calls.last_mut() {
+                Some((last, count)) if last == token => *count += 1,
+                _ => calls.push((*token, 1)),
+            }
+        }
+
+        let omitted = calls.len().saturating_sub(2 * TRACE_FRAMES_SHOWN);
+        let mut locations = Vec::new();
+
+        for (idx, (token, count)) in calls.iter().enumerate() {
+            if (TRACE_FRAMES_SHOWN..TRACE_FRAMES_SHOWN + omitted).contains(&idx) {
+                if idx == TRACE_FRAMES_SHOWN {
+                    locations.push(format!("... {omitted} more calls"));
+                }
+
+                continue;
+            }
+
+            let mut location = self.location(source, &mut sources, token);
+
+            if *count > 1 {
+                let _ = write!(location, " ({count} times)");
+            }
+
+            locations.push(location);
+        }
+
+        locations
+    }
+
+    /// Returns the imported file the token is from, unless it is from the given source
+    pub fn file_of(&self, source: &Source, token: &Token) -> Option<&FileRef> {
+        self.trace
+            .files
+            .get(token.file.wrapping_sub(1))
+            .filter(|_| token.file != source.file)
+    }
+
+    /// Formats the position and the lexeme of the token, prefixed by the file it is from,
+    /// unless it is from the given source
+    fn location<'a>(
+        &'a self,
+        source: &'a Source,
+        sources: &mut HashMap<FileId, Source<'a>>,
+        token: &Token,
+    ) -> String {
+        let Some(file) = self.file_of(source, token) else {
+            return Self::position(source, token);
+        };
+
+        let source = sources
+            .entry(token.file)
+            .or_insert_with(|| Source::in_file(&file.code, token.file));
+
+        format!("{}:{}", file.name, Self::position(source, token))
+    }
+
+    fn position(source: &Source, token: &Token) -> String {
+        let line_col = source.start(token);
+        format!(
+            "{}:{} => '{}'",
             line_col.line + 1,
             line_col.col + 1,
-            source.lexeme(&self.token),
-            self.error
+            source.lexeme(token)
         )
     }
 }
@@ -69,16 +214,57 @@ impl Error {
         match self {
             Error::ExpectedIdentifierAsVariableName
             | Error::ExpectedIdentifierAsFunctionArgument
-            | Error::ExpectedBeInAssignment
-            | Error::ExpectedInAfterAssignment
+            | Error::ExpectedBeInAssignment(_)
+            | Error::ExpectedInAfterAssignment(_)
             | Error::ExpectedEofAfterExpression
             | Error::ExpectedExpressionFound(_)
-            | Error::ExpectedEndAfterDoBlock
-            | Error::ExpectedDoAsFunctionBody
+            | Error::ExpectedEndAfterDoBlock(_)
+            | Error::ExpectedDoAsFunctionBody(_)
+            | Error::UnterminatedString
+            | Error::UnterminatedComment
             | Error::IdentifierIsNotDefined(_) => ErrorType::Compile,
             _ => ErrorType::Runtime,
         }
     }
+
+    /// Returns the tokens, that are related to the error, with a description of each
+    pub fn labels(&self) -> Vec<(Token, &'static str)> {
+        match self {
+            Error::ExpectedBeInAssignment(let_token)
+            | Error::ExpectedInAfterAssignment(let_token) => {
+                vec![(*let_token, "The 'let' starts here")]
+            }
+            Error::ExpectedEndAfterDoBlock(do_token) => {
+                vec![(*do_token, "This 'do' block is never closed")]
+            }
+            Error::ExpectedDoAsFunctionBody(fn_token) => {
+                vec![(*fn_token, "The function starts here")]
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    /// Returns a hint on how to fix the error, if there is a common cause
+    pub fn hint(&self) -> Option<&'static str> {
+        match self {
+            Error::ExpectedEndAfterDoBlock(_) => {
+                Some("Every 'do' needs a matching 'end', including the 'do' of a function body.")
+            }
+            Error::ExpectedInAfterAssignment(_) => {
+                Some("A 'let' binds a single value, so the value must be followed by 'in'.")
+            }
+            Error::IdentifierIsNotDefined(_) => Some(
+                "Bindings are only visible after their 'in', function arguments only in the function body.",
+            ),
+            Error::UnterminatedString | Error::UnterminatedComment => {
+                Some("'ing' and 'ment' must be separate words, surrounded by whitespace.")
+            }
+            Error::ValueNotCallable(_) => {
+                Some("Only functions can be called. Did you pass too many arguments?")
+            }
+            _ => None,
+        }
+    }
 }
 
 impl Display for Error {
@@ -90,18 +276,20 @@ impl Display for Error {
             Self::ExpectedIdentifierAsFunctionArgument => {
                 f.write_str("Expected an identifier for a function argument after 'fn'.")
             }
-            Self::ExpectedBeInAssignment => f.write_str("Expected 'be' in 'let' assignment."),
-            Self::ExpectedInAfterAssignment => f.write_str("Expected 'in' after assignment."),
+            Self::ExpectedBeInAssignment(_) => f.write_str("Expected 'be' in 'let' assignment."),
+            Self::ExpectedInAfterAssignment(_) => f.write_str("Expected 'in' after assignment."),
             Self::ExpectedEofAfterExpression => {
                 f.write_str("Expected Eof after expression. Did you miss a function call ('of')?")
             }
             Self::ExpectedExpressionFound(token) => {
                 f.write_fmt(format_args!("Expected expression, found {:?}", token))
             }
-            Self::ExpectedEndAfterDoBlock => {
+            Self::ExpectedEndAfterDoBlock(_) => {
                 f.write_str("Expected 'end' after do block. Did you miss a function call ('of')?")
             }
-            Self::ExpectedDoAsFunctionBody => f.write_str("Expected do to start function body."),
+            Self::ExpectedDoAsFunctionBody(_) => f.write_str("Expected do to start function body."),
+            Self::UnterminatedString => f.write_str("String is never terminated with 'ing'."),
+            Self::UnterminatedComment => f.write_str("Comment is never terminated with 'ment'."),
             Self::ValueNotCallable(value) => {
                 f.write_fmt(format_args!("{} value is not callable.", value.get_type()))
             }
@@ -111,8 +299,36 @@ impl Display for Error {
             Self::ArgumentToOperatorMustBeANumber(str) => {
                 f.write_fmt(format_args!("Argument to {str} must be a number!"))
             }
+            Self::ArgumentToOperatorMustBeAList(str) => {
+                f.write_fmt(format_args!("Argument to {str} must be a list!"))
+            }
+            Self::ArgumentToOperatorMustBeAString(str) => {
+                f.write_fmt(format_args!("Argument to {str} must be a string!"))
+            }
+            Self::ArgumentToOperatorMustBeARecord(str) => {
+                f.write_fmt(format_args!("Argument to {str} must be a record!"))
+            }
+            Self::KeyNotInRecord(key) => {
+                f.write_fmt(format_args!("Key '{key}' is not in the record."))
+            }
+            Self::ListIsEmpty(str) => {
+                f.write_fmt(format_args!("Argument to {str} must not be an empty list!"))
+            }
+            Self::StringIsEmpty(str) => f.write_fmt(format_args!(
+                "Argument to {str} must not be an empty string!"
+            )),
+            Self::InvalidCharCode(code) => {
+                f.write_fmt(format_args!("{code} is not a valid character code."))
+            }
+            Self::NumberHasNoWords(number) => {
+                f.write_fmt(format_args!("{number} can't be written in words."))
+            }
+            Self::IndexOutOfBounds(index, len) => f.write_fmt(format_args!(
+                "Index {index} is out of bounds for a length of {len}."
+            )),
             Self::ValueDependsOnItself => f.write_str("Calculation of value depends on itself."),
             Self::OutputNotWritable => f.write_str("Outputstream is not writable."),
+            Self::InputNotReadable => f.write_str("Inputstream is not readable."),
             Self::ArgumentToImportMustBeAString => {
                 f.write_str("Argument to 'Import' must be a string.")
             }
@@ -122,6 +338,20 @@ impl Display for Error {
             Self::ErrorInImportedFile(file, error) => {
                 f.write_fmt(format_args!("Error in imported file '{file}': \n{error}"))
             }
+            Self::StepLimitExceeded(limit) => f.write_fmt(format_args!(
+                "Evaluation exceeded the limit of {limit} steps."
+            )),
+            Self::ThunkLimitExceeded(limit) => f.write_fmt(format_args!(
+                "Evaluation exceeded the limit of {limit} lazy values alive at the same time."
+            )),
+            Self::TimeLimitExceeded(limit) => f.write_fmt(format_args!(
+                "Evaluation exceeded the time limit of {}s.",
+                limit.as_secs_f64()
+            )),
+            Self::CyclicImport(chain) => f.write_fmt(format_args!(
+                "Import cycle detected: '{}'.",
+                chain.join("' -> '")
+            )),
         }
     }
 }
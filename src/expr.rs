@@ -15,7 +15,14 @@ pub enum Expr {
     Identifier(IdentRef),
     Call(ExprRef, ExprRef),
     Value(ValueRef),
-    Fn(ExprRef),
+    /// A global binding, like the default bindings, that the compiler resolved to its value
+    Global(ValueRef),
+    /// The body of a function and the slots of the values it captures.
+    /// The captures are only known after closure conversion, see [`crate::closure`]
+    Fn(ExprRef, Vec<IdentRef>),
+    /// An argument, that is always forced by the function it is passed to.
+    /// It is evaluated right away, instead of being wrapped in a lazy call
+    Strict(ExprRef),
 }
 
 impl Deref for AnnotatedExpr {
@@ -48,4 +55,12 @@ impl AnnotatedExpr {
     pub fn expr(&self) -> &Expr {
         &self.expr
     }
+
+    /// Returns the token of the called function, if the expression is a call
+    pub fn callee_token(&self) -> Token {
+        match &self.expr {
+            Expr::Call(lhs, _) => lhs.token,
+            _ => self.token,
+        }
+    }
 }
@@ -1,13 +1,19 @@
+use std::collections::HashMap;
 use std::fs;
-use std::io::{Read, Write};
+use std::io::{ErrorKind, Read, Write};
+use std::mem;
 use std::ops::Deref;
 use std::path::PathBuf;
-use std::sync::{Arc, Mutex, OnceLock};
+use std::sync::{Arc, Mutex};
 
 use rust_embed::Embed;
 
-use crate::source::Source;
-use crate::{builtin, evaluate_code};
+use crate::bytecode::{Code, CodeRef};
+use crate::limits::{Budget, Limits};
+use crate::number::Number;
+use crate::source::{FileRef, Source, SourceFile};
+use crate::token::Token;
+use crate::{EvalOptions, builtin, compile_with};
 
 use crate::error::AnnotatedError;
 use crate::{
@@ -16,10 +22,11 @@ use crate::{
     error::Error,
     expr::{Expr, ExprRef},
     fun_val, identifier, unit,
-    value::{BuiltIn, Value, ValueRef},
+    value::{BuiltIn, Callable, List, Partial, ThunkState, Value, ValueRef},
 };
 
 pub type ValueResult = Result<ValueRef, AnnotatedError>;
+
 pub type Input<'a> = Arc<Mutex<Box<dyn Read + Send + 'a>>>;
 pub type Output<'a> = Arc<Mutex<Box<dyn Write + Send + 'a>>>;
 
@@ -29,77 +36,747 @@ pub type Output<'a> = Arc<Mutex<Box<dyn Write + Send + 'a>>>;
 #[include("*.mrbl")]
 struct Lang;
 
+/// Work that is left to do, after the value currently being forced is unwrapped
+enum Continuation {
+    /// Call the unwrapped value with the argument, evaluated in the environment
+    Apply(ExprRef, EnvRef, Token),
+    /// Call the function with the unwrapped value as its argument
+    Call(ValueRef, Token),
+    /// Store the unwrapped value as the result of the lazy call
+    Memoize(ValueRef),
+}
+
+impl Continuation {
+    /// Returns the token of the function, whose call is waiting for the unwrapped value
+    fn token(&self) -> Token {
+        match self {
+            Continuation::Apply(_, _, token) | Continuation::Call(_, token) => *token,
+            Continuation::Memoize(lazy) => match lazy.thunk() {
+                Some(thunk) => match thunk.state() {
+                    ThunkState::Pending(call, _) => call.callee_token(),
+                    ThunkState::Evaluating(token) => token,
+                    ThunkState::Evaluated(_) => Token::default(),
+                },
+                None => unreachable!("Only lazy calls are memoized"),
+            },
+        }
+    }
+}
+
+/// The next thing to unwrap
+enum Unwrap {
+    Value(ValueRef),
+    /// An expression, that is forced right away and thus evaluated in place
+    Expr(ExprRef, EnvRef),
+}
+
+/// The modules imported during a single run
+///
+/// Modules are identified by their canonical path, or by their name for the modules in `lang/`
+#[derive(Default)]
+struct Modules {
+    /// The values of all modules, that have been evaluated
+    cache: HashMap<String, ValueRef>,
+    /// The modules that are currently being evaluated, paired with the name they were imported as
+    loading: Vec<(String, String)>,
+    /// The sources of all imported files, the file id of a token is its index plus one
+    files: Vec<FileRef>,
+}
+
+/// The way expressions are executed
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Backend {
+    /// Walks the expression tree directly
+    #[default]
+    TreeWalking,
+    /// Compiles the expression to bytecode and executes it on a virtual machine
+    Bytecode,
+}
+
 pub struct Interpreter<'a> {
     execution_path: PathBuf,
-    _input: Input<'a>,
+    input: Input<'a>,
     output: Output<'a>,
+    modules: Modules,
+    backend: Backend,
+    /// Whether imported files are optimized
+    optimize: bool,
+    pub(crate) budget: Budget,
+    /// The bytecode of function bodies and lazy calls, keyed by the address of their expression
+    pub(crate) compiled: HashMap<usize, (ExprRef, CodeRef)>,
 }
 
 impl<'a> Interpreter<'a> {
     pub fn interpret(&mut self, expr: ExprRef) -> ValueResult {
-        let value = self.evaluate(expr, Environment::root())?;
-        self.unwrap_lazy(value)
+        let result = match self.backend {
+            Backend::TreeWalking => self
+                .evaluate(expr, Environment::root())
+                .and_then(|value| self.unwrap_lazy(value)),
+            Backend::Bytecode => self.run(Code::compile(&expr).new_ref()),
+        };
+
+        // Values of imported files may fail long after the file was evaluated
+        result.map_err(|err| err.with_files(self.modules.files.clone()))
     }
 
     fn evaluate(&mut self, expr: ExprRef, environment: EnvRef) -> ValueResult {
         match expr.as_ref().deref() {
-            Expr::Call(lhs, rhs) => {
-                Ok(
-                    Value::LazyCall(lhs.clone(), rhs.clone(), environment, OnceLock::new())
-                        .new_ref(),
-                )
+            Expr::Call(_, _) => {
+                let token = expr.token;
+                let thunk = self
+                    .budget
+                    .thunk(expr, environment)
+                    .map_err(|err| err.annotate(token))?;
+
+                Ok(Value::LazyCall(thunk).new_ref())
             }
             Expr::Identifier(ident) => Ok(environment.find(*ident).clone()),
-            Expr::Value(v) => Ok(v.clone()),
-            Expr::Fn(body) => Ok(Value::Fn(body.clone(), environment).new_ref()),
+            Expr::Value(v) | Expr::Global(v) => Ok(v.clone()),
+            Expr::Fn(body, captures) => {
+                Ok(Value::Fn(body.clone(), environment.capture(captures)).new_ref())
+            }
+            Expr::Strict(inner) => self.evaluate(inner.clone(), environment),
         }
     }
 
-    fn evaluate_fn(&mut self, expr: ExprRef, environment: EnvRef, value: ValueRef) -> ValueResult {
-        self.evaluate(expr, Environment::extend(environment, value))
+    /// Forces a value until it is no longer a [`Value::LazyCall`]
+    ///
+    /// Nested lazy calls are not forced through recursion, but by keeping the pending work
+    /// on an explicit stack of [`Continuation`]s. This way the depth of recursion in a marble
+    /// program is only bounded by the available heap memory and not by the native stack.
+    ///
+    /// Expressions, that are forced anyway, are evaluated in place instead of allocating a
+    /// lazy call for them. These are callees, arguments to builtins and [`Expr::Strict`] arguments.
+    ///
+    /// Errors are annotated with the calls of all pending continuations as their stack trace.
+    /// Calls in tail position don't leave a continuation, so they don't show up in the trace.
+    fn unwrap_lazy(&mut self, value: ValueRef) -> ValueResult {
+        let mut stack = Vec::new();
+
+        self.unwrap_with(value, &mut stack).map_err(|err| {
+            let trace = stack.iter().rev().map(Continuation::token).collect();
+            err.with_trace(trace)
+        })
     }
 
-    fn unwrap_lazy(&mut self, value: ValueRef) -> ValueResult {
-        let Value::LazyCall(lhs_expr, rhs_expr, env, cell) = &*value else {
-            return Ok(value);
+    fn unwrap_with(&mut self, value: ValueRef, stack: &mut Vec<Continuation>) -> ValueResult {
+        let mut next = Unwrap::Value(value);
+        let mut token = Token::default();
+
+        loop {
+            if let Unwrap::Expr(expr, _) = &next {
+                token = expr.token;
+            }
+
+            self.budget.step().map_err(|err| err.annotate(token))?;
+
+            let value = match next {
+                Unwrap::Expr(expr, env) => match expr.expr() {
+                    Expr::Call(lhs, rhs) => {
+                        stack.push(Continuation::Apply(rhs.clone(), env.clone(), lhs.token));
+                        next = Unwrap::Expr(lhs.clone(), env);
+                        continue;
+                    }
+                    Expr::Strict(inner) => {
+                        next = Unwrap::Expr(inner.clone(), env);
+                        continue;
+                    }
+                    _ => self.evaluate(expr, env)?,
+                },
+                Unwrap::Value(value) => value,
+            };
+
+            if let Some(thunk) = value.thunk() {
+                next = match thunk.take()? {
+                    ThunkState::Evaluated(result) => Unwrap::Value(result),
+                    ThunkState::Pending(call, env) => {
+                        // If nothing else references the lazy call, its value can never be
+                        // observed again. Skipping the memoization keeps tail calls from
+                        // piling up continuations and frees the lazy call right away.
+                        if ValueRef::strong_count(&value) > 1 {
+                            stack.push(Continuation::Memoize(value.clone()));
+                        }
+
+                        Unwrap::Expr(call, env)
+                    }
+                    ThunkState::Evaluating(_) => unreachable!("Lazy call was taken twice"),
+                };
+                continue;
+            }
+
+            // The value is fully unwrapped, so it is passed to the innermost continuation
+            let Some(continuation) = stack.pop() else {
+                return Ok(value);
+            };
+
+            next = match continuation {
+                Continuation::Apply(rhs, env, token) => match (value.callable(), rhs.expr()) {
+                    (Some(_), Expr::Strict(argument)) => {
+                        stack.push(Continuation::Call(value, token));
+                        Unwrap::Expr(argument.clone(), env)
+                    }
+                    (Some(Callable::Fn(body, fn_env)), _) => {
+                        let argument = self.evaluate(rhs, env)?;
+                        Unwrap::Expr(body.clone(), Environment::extend(fn_env.clone(), argument))
+                    }
+                    (Some(Callable::Builtin(built_in)), _) if !built_in.forces_argument() => {
+                        let argument = self.evaluate(rhs, env)?;
+                        Unwrap::Value(
+                            self.evaluate_builtin(built_in, argument)
+                                .map_err(|err| err.annotate(token))?,
+                        )
+                    }
+                    (Some(Callable::Builtin(_) | Callable::Partial(_)), _) => {
+                        stack.push(Continuation::Call(value, token));
+                        Unwrap::Expr(rhs, env)
+                    }
+                    (None, _) => return Err(Error::ValueNotCallable(value).annotate(token)),
+                },
+                Continuation::Call(function, token) => match function.callable() {
+                    Some(Callable::Fn(body, fn_env)) => {
+                        Unwrap::Expr(body.clone(), Environment::extend(fn_env.clone(), value))
+                    }
+                    Some(Callable::Builtin(built_in)) => Unwrap::Value(
+                        self.evaluate_builtin(built_in, value)
+                            .map_err(|err| err.annotate(token))?,
+                    ),
+                    Some(Callable::Partial(partial)) => Unwrap::Value(
+                        Self::evaluate_partial(partial, value)
+                            .map_err(|err| err.annotate(token))?,
+                    ),
+                    None => unreachable!("Only functions are called"),
+                },
+                Continuation::Memoize(lazy) => {
+                    let Some(thunk) = lazy.thunk() else {
+                        unreachable!("Only lazy calls are memoized")
+                    };
+
+                    Unwrap::Value(thunk.memoize(value))
+                }
+            };
+        }
+    }
+
+    pub(crate) fn evaluate_builtin(
+        &mut self,
+        function: &BuiltIn,
+        rhs: ValueRef,
+    ) -> Result<ValueRef, Error> {
+        match function {
+            // Values are shown and compared as a whole, so all of their elements are unwrapped first
+            BuiltIn::Print
+            | BuiltIn::PrintLn
+            | BuiltIn::ToString
+            | BuiltIn::Is
+            | BuiltIn::IsNot
+            | BuiltIn::IsOf(_)
+            | BuiltIn::IsNotOf(_) => {
+                let pending = Arc::new(Mutex::new(vec![rhs.clone()]));
+                self.unwrap_elements(function, rhs, pending)
+            }
+
+            BuiltIn::Add => Ok(ValueRef::Partial(Partial::AddOf(
+                rhs.number_for_operator("Add")?.clone(),
+            ))),
+            BuiltIn::Sub => Ok(ValueRef::Partial(Partial::SubOf(
+                rhs.number_for_operator("Sub")?.clone(),
+            ))),
+            BuiltIn::Mul => Ok(ValueRef::Partial(Partial::MulOf(
+                rhs.number_for_operator("Mul")?.clone(),
+            ))),
+            BuiltIn::Div => Ok(ValueRef::Partial(Partial::DivOf(
+                rhs.number_for_operator("Div")?.clone(),
+            ))),
+            BuiltIn::Mod => Ok(ValueRef::Partial(Partial::ModOf(
+                rhs.number_for_operator("Mod")?.clone(),
+            ))),
+            BuiltIn::Pow => Ok(ValueRef::Partial(Partial::PowOf(
+                rhs.number_for_operator("Pow")?.clone(),
+            ))),
+            BuiltIn::Min => Ok(ValueRef::Partial(Partial::MinOf(
+                rhs.number_for_operator("Min")?.clone(),
+            ))),
+            BuiltIn::Max => Ok(ValueRef::Partial(Partial::MaxOf(
+                rhs.number_for_operator("Max")?.clone(),
+            ))),
+            BuiltIn::LessThan => Ok(ValueRef::Partial(Partial::LessThanOf(
+                rhs.number_for_operator("LessThan")?.clone(),
+            ))),
+            BuiltIn::GreaterThan => Ok(ValueRef::Partial(Partial::GreaterThanOf(
+                rhs.number_for_operator("GreaterThan")?.clone(),
+            ))),
+
+            BuiltIn::Floor => Ok(ValueRef::Number(rhs.number_for_operator("Floor")?.floor())),
+            BuiltIn::Ceil => Ok(ValueRef::Number(rhs.number_for_operator("Ceil")?.ceil())),
+            BuiltIn::Round => Ok(ValueRef::Number(rhs.number_for_operator("Round")?.round())),
+            BuiltIn::Abs => Ok(ValueRef::Number(rhs.number_for_operator("Abs")?.abs())),
+            BuiltIn::Sqrt => Ok(ValueRef::Number(rhs.number_for_operator("Sqrt")?.sqrt())),
+            BuiltIn::Import => match rhs.shared() {
+                Some(Value::String(source)) => self.import(source.clone()),
+                _ => Err(Error::ArgumentToImportMustBeAString),
+            },
+
+            BuiltIn::Cons => Ok(Value::Builtin(BuiltIn::ConsOf(rhs)).new_ref()),
+            BuiltIn::ConsOf(head) => {
+                rhs.list_for_operator("Cons")?;
+                Ok(Value::List(List::Cons(head.clone(), rhs)).new_ref())
+            }
+            BuiltIn::Head => match rhs.list_for_operator("Head")? {
+                List::Cons(head, _) => Ok(head.clone()),
+                List::Empty => Err(Error::ListIsEmpty("Head")),
+            },
+            BuiltIn::Tail => match rhs.list_for_operator("Tail")? {
+                List::Cons(_, tail) => Ok(tail.clone()),
+                List::Empty => Err(Error::ListIsEmpty("Tail")),
+            },
+            BuiltIn::IsEmpty => Ok(match rhs.list_for_operator("IsEmpty")? {
+                List::Empty => builtin::TRUE.clone(),
+                List::Cons(_, _) => builtin::FALSE.clone(),
+            }),
+            BuiltIn::Length => {
+                let length = rhs.list_for_operator("Length")?.iter().count();
+                Ok(ValueRef::Number(Number::Integer(length as i64)))
+            }
+            BuiltIn::Nth => Ok(ValueRef::Partial(Partial::NthOf(
+                rhs.number_for_operator("Nth")?.clone(),
+            ))),
+            BuiltIn::Append => {
+                rhs.list_for_operator("Append")?;
+                Ok(Value::Builtin(BuiltIn::AppendOf(rhs)).new_ref())
+            }
+            BuiltIn::AppendOf(lhs) => {
+                rhs.list_for_operator("Append")?;
+
+                let Some(Value::List(lhs)) = lhs.shared() else {
+                    unreachable!("Only lists are appended to")
+                };
+
+                let elements: Vec<_> = lhs.iter().cloned().collect();
+                Ok(List::prepend(&elements, rhs))
+            }
+
+            BuiltIn::Get => {
+                Ok(Value::Builtin(BuiltIn::GetOf(rhs.string_for_operator("Get")?)).new_ref())
+            }
+            BuiltIn::GetOf(key) => rhs
+                .record_for_operator("Get")?
+                .get(key)
+                .cloned()
+                .ok_or_else(|| Error::KeyNotInRecord(key.clone())),
+            BuiltIn::Has => {
+                Ok(Value::Builtin(BuiltIn::HasOf(rhs.string_for_operator("Has")?)).new_ref())
+            }
+            BuiltIn::HasOf(key) => Ok(builtin::boolean(
+                rhs.record_for_operator("Has")?.contains_key(key),
+            )),
+            BuiltIn::Set => {
+                Ok(Value::Builtin(BuiltIn::SetOf(rhs.string_for_operator("Set")?)).new_ref())
+            }
+            BuiltIn::SetOf(key) => {
+                Ok(Value::Builtin(BuiltIn::SetOfValue(key.clone(), rhs)).new_ref())
+            }
+            BuiltIn::SetOfValue(key, value) => {
+                // Records are persistent, so the record is copied instead of changed
+                let mut record = rhs.record_for_operator("Set")?.clone();
+                record.insert(key.clone(), value.clone());
+                Ok(Value::Record(record).new_ref())
+            }
+            BuiltIn::Keys => {
+                let keys: Vec<_> = rhs
+                    .record_for_operator("Keys")?
+                    .keys()
+                    .map(|key| Value::String(key.clone()).new_ref())
+                    .collect();
+                Ok(List::prepend(&keys, builtin::NIL.clone()))
+            }
+
+            BuiltIn::Concat => {
+                Ok(Value::Builtin(BuiltIn::ConcatOf(rhs.string_for_operator("Concat")?)).new_ref())
+            }
+            BuiltIn::ConcatOf(lhs) => {
+                Ok(Value::String(lhs.clone() + &rhs.string_for_operator("Concat")?).new_ref())
+            }
+            BuiltIn::StringLength => {
+                let length = rhs.string_for_operator("StringLength")?.chars().count();
+                Ok(ValueRef::Number(Number::Integer(length as i64)))
+            }
+            BuiltIn::Substring => Ok(ValueRef::Partial(Partial::SubstringOf(
+                rhs.number_for_operator("Substring")?.clone(),
+            ))),
+            BuiltIn::SubstringOfRange(from, to) => {
+                let string = rhs.string_for_operator("Substring")?;
+                let length = string.chars().count();
+
+                // Fractional and negative indices are never in bounds
+                let to = to
+                    .as_index()
+                    .filter(|to| *to <= length)
+                    .ok_or_else(|| Error::IndexOutOfBounds(to.clone(), length))?;
+                let from = from
+                    .as_index()
+                    .filter(|from| *from <= to)
+                    .ok_or_else(|| Error::IndexOutOfBounds(from.clone(), length))?;
+
+                let substring = string.chars().skip(from).take(to - from).collect();
+
+                Ok(Value::String(substring).new_ref())
+            }
+            BuiltIn::Split => {
+                Ok(Value::Builtin(BuiltIn::SplitOf(rhs.string_for_operator("Split")?)).new_ref())
+            }
+            BuiltIn::SplitOf(separator) => {
+                let string = rhs.string_for_operator("Split")?;
+
+                // An empty separator splits the string into its characters
+                let parts: Vec<_> = if separator.is_empty() {
+                    string
+                        .chars()
+                        .map(|c| Value::String(c.to_string()).new_ref())
+                        .collect()
+                } else {
+                    string
+                        .split(separator.as_str())
+                        .map(|part| Value::String(part.to_string()).new_ref())
+                        .collect()
+                };
+
+                Ok(List::prepend(&parts, builtin::NIL.clone()))
+            }
+            BuiltIn::Join => {
+                Ok(Value::Builtin(BuiltIn::JoinOf(rhs.string_for_operator("Join")?)).new_ref())
+            }
+            BuiltIn::JoinOf(separator) => {
+                rhs.list_for_operator("Join")?;
+                self.join(separator.clone(), String::new(), rhs)
+            }
+            BuiltIn::JoinOnto(separator, joined, tail) => {
+                let mut joined = joined.clone();
+                joined.push_str(&rhs.string_for_operator("Join")?);
+
+                if let Some(Value::List(List::Cons(_, _))) = tail.shared() {
+                    joined.push_str(separator);
+                }
+
+                self.join(separator.clone(), joined, tail.clone())
+            }
+            BuiltIn::Trim => {
+                let string = rhs.string_for_operator("Trim")?;
+                Ok(Value::String(string.trim().to_string()).new_ref())
+            }
+            BuiltIn::Contains => Ok(Value::Builtin(BuiltIn::ContainsOf(
+                rhs.string_for_operator("Contains")?,
+            ))
+            .new_ref()),
+            BuiltIn::ContainsOf(part) => Ok(builtin::boolean(
+                rhs.string_for_operator("Contains")?.contains(part.as_str()),
+            )),
+            BuiltIn::StartsWith => Ok(Value::Builtin(BuiltIn::StartsWithOf(
+                rhs.string_for_operator("StartsWith")?,
+            ))
+            .new_ref()),
+            BuiltIn::StartsWithOf(prefix) => Ok(builtin::boolean(
+                rhs.string_for_operator("StartsWith")?
+                    .starts_with(prefix.as_str()),
+            )),
+            BuiltIn::Upper => {
+                Ok(Value::String(rhs.string_for_operator("Upper")?.to_uppercase()).new_ref())
+            }
+            BuiltIn::Lower => {
+                Ok(Value::String(rhs.string_for_operator("Lower")?.to_lowercase()).new_ref())
+            }
+            BuiltIn::CharCode => {
+                let code = rhs
+                    .string_for_operator("CharCode")?
+                    .chars()
+                    .next()
+                    .ok_or(Error::StringIsEmpty("CharCode"))?;
+                Ok(ValueRef::Number(Number::Integer(code as i64)))
+            }
+            BuiltIn::FromCharCode => {
+                let code = rhs.number_for_operator("FromCharCode")?;
+
+                let char = code
+                    .as_index()
+                    .and_then(|code| u32::try_from(code).ok())
+                    .and_then(char::from_u32)
+                    .ok_or_else(|| Error::InvalidCharCode(code.clone()))?;
+
+                Ok(Value::String(char.to_string()).new_ref())
+            }
+
+            BuiltIn::ToWords => {
+                let number = rhs.number_for_operator("ToWords")?;
+                let words = number
+                    .to_words()
+                    .ok_or_else(|| Error::NumberHasNoWords(number.clone()))?;
+
+                Ok(Value::String(words).new_ref())
+            }
+            BuiltIn::ParseNumber => Ok(Number::from_digits(
+                rhs.string_for_operator("ParseNumber")?.trim(),
+            )
+            .map_or_else(
+                || builtin::NONE.clone(),
+                |number| builtin::some(ValueRef::Number(number)),
+            )),
+            BuiltIn::ParseWords => {
+                let word = rhs.string_for_operator("ParseWords")?;
+                let word = word.trim();
+
+                Ok(Number::from_words(word)
+                    .or_else(|| Number::from_ordinal_words(word))
+                    .map_or_else(
+                        || builtin::NONE.clone(),
+                        |number| builtin::some(ValueRef::Number(number)),
+                    ))
+            }
+
+            BuiltIn::ReadLine => {
+                let line = self.read_line()?;
+                let option = line.map_or_else(
+                    || builtin::NONE.clone(),
+                    |line| builtin::some(Value::String(line).new_ref()),
+                );
+
+                self.apply(rhs, option)
+            }
+            BuiltIn::ReadChar => {
+                let char = self.read_char()?;
+                let option = char.map_or_else(
+                    || builtin::NONE.clone(),
+                    |char| builtin::some(Value::String(char).new_ref()),
+                );
+
+                self.apply(rhs, option)
+            }
+            BuiltIn::ReadAll => {
+                let mut bytes = Vec::new();
+                self.input
+                    .lock()
+                    .unwrap()
+                    .read_to_end(&mut bytes)
+                    .map_err(|_| Error::InputNotReadable)?;
+                let all = Value::String(String::from_utf8_lossy(&bytes).into_owned());
+
+                self.apply(rhs, all.new_ref())
+            }
+            BuiltIn::UnwrapFor(function, value, pending) => {
+                pending.lock().unwrap().push(rhs);
+                self.unwrap_elements(function, value.clone(), pending.clone())
+            }
+        }
+    }
+
+    /// Calls a builtin, that has already been given a number
+    pub(crate) fn evaluate_partial(function: &Partial, rhs: ValueRef) -> Result<ValueRef, Error> {
+        match function {
+            Partial::IsOf(lhs) => Ok(builtin::boolean(
+                matches!(&rhs, ValueRef::Number(rhs) if lhs == rhs),
+            )),
+            Partial::IsNotOf(lhs) => Ok(builtin::boolean(
+                !matches!(&rhs, ValueRef::Number(rhs) if lhs == rhs),
+            )),
+
+            Partial::AddOf(lhs) => Ok(ValueRef::Number(lhs + rhs.number_for_operator("Add")?)),
+            Partial::SubOf(lhs) => Ok(ValueRef::Number(lhs - rhs.number_for_operator("Sub")?)),
+            Partial::MulOf(lhs) => Ok(ValueRef::Number(lhs * rhs.number_for_operator("Mul")?)),
+            Partial::DivOf(lhs) => Ok(ValueRef::Number(lhs / rhs.number_for_operator("Div")?)),
+
+            // The remainder is never negative, so counting with Mod works for negative numbers
+            Partial::ModOf(lhs) => Ok(ValueRef::Number(
+                lhs.rem_euclid(rhs.number_for_operator("Mod")?),
+            )),
+            Partial::PowOf(lhs) => Ok(ValueRef::Number(lhs.pow(rhs.number_for_operator("Pow")?))),
+            Partial::MinOf(lhs) => Ok(ValueRef::Number(lhs.min(rhs.number_for_operator("Min")?))),
+            Partial::MaxOf(lhs) => Ok(ValueRef::Number(lhs.max(rhs.number_for_operator("Max")?))),
+            Partial::LessThanOf(lhs) => {
+                Ok(builtin::boolean(lhs < rhs.number_for_operator("LessThan")?))
+            }
+            Partial::GreaterThanOf(lhs) => Ok(builtin::boolean(
+                lhs > rhs.number_for_operator("GreaterThan")?,
+            )),
+
+            Partial::NthOf(index) => {
+                let list = rhs.list_for_operator("Nth")?;
+
+                // Fractional and negative indices are never in bounds
+                let element = index.as_index().and_then(|index| list.iter().nth(index));
+
+                element
+                    .cloned()
+                    .ok_or_else(|| Error::IndexOutOfBounds(index.clone(), list.iter().count()))
+            }
+            Partial::SubstringOf(from) => Ok(Value::Builtin(BuiltIn::SubstringOfRange(
+                from.clone(),
+                rhs.number_for_operator("Substring")?.clone(),
+            ))
+            .new_ref()),
+        }
+    }
+
+    /// Returns a lazy call of the function with the argument
+    ///
+    /// Builtins can't call functions themselves, instead the lazy call is unwrapped after the
+    /// builtin returns it.
+    fn apply(&self, function: ValueRef, argument: ValueRef) -> Result<ValueRef, Error> {
+        let environment = Environment::extend(Environment::new(vec![function]), argument);
+        let thunk = self.budget.thunk(builtin::APPLY.clone(), environment)?;
+
+        Ok(Value::LazyCall(thunk).new_ref())
+    }
+
+    /// Reads the next byte of the input, if there is one
+    fn read_byte(input: &mut dyn Read) -> Result<Option<u8>, Error> {
+        let mut byte = [0];
+
+        loop {
+            match input.read(&mut byte) {
+                Ok(0) => return Ok(None),
+                Ok(_) => return Ok(Some(byte[0])),
+                Err(err) if err.kind() == ErrorKind::Interrupted => {}
+                Err(_) => return Err(Error::InputNotReadable),
+            }
+        }
+    }
+
+    /// Reads the input up to and including the next line break
+    ///
+    /// The input is read byte by byte, so nothing after the line is consumed.
+    /// Returns `None` at the end of the input.
+    fn read_line(&self) -> Result<Option<String>, Error> {
+        let mut input = self.input.lock().unwrap();
+        let mut line = Vec::new();
+
+        while let Some(byte) = Self::read_byte(&mut *input)? {
+            if byte == b'\n' {
+                if line.last() == Some(&b'\r') {
+                    line.pop();
+                }
+
+                return Ok(Some(String::from_utf8_lossy(&line).into_owned()));
+            }
+
+            line.push(byte);
+        }
+
+        Ok((!line.is_empty()).then(|| String::from_utf8_lossy(&line).into_owned()))
+    }
+
+    /// Reads a single utf-8 encoded character. Returns `None` at the end of the input
+    fn read_char(&self) -> Result<Option<String>, Error> {
+        let mut input = self.input.lock().unwrap();
+
+        let Some(first) = Self::read_byte(&mut *input)? else {
+            return Ok(None);
         };
 
-        if let Some(value) = cell.get() {
-            return Ok(value.clone());
+        // The leading ones of the first byte are the length of the encoded character
+        let length = first.leading_ones().clamp(1, 4) as usize;
+        let mut bytes = vec![first];
+
+        while bytes.len() < length {
+            match Self::read_byte(&mut *input)? {
+                Some(byte) => bytes.push(byte),
+                None => break,
+            }
         }
 
-        let lhs = self.evaluate(lhs_expr.clone(), env.clone())?;
-        let rhs = self.evaluate(rhs_expr.clone(), env.clone())?;
+        Ok(Some(String::from_utf8_lossy(&bytes).into_owned()))
+    }
+
+    /// Joins the strings of the list onto the already joined string
+    ///
+    /// Builtins can't unwrap values themselves. Once an element isn't unwrapped yet, a lazy call
+    /// is returned, that continues joining with [`BuiltIn::JoinOnto`] after unwrapping it.
+    fn join(
+        &self,
+        separator: String,
+        mut joined: String,
+        mut list: ValueRef,
+    ) -> Result<ValueRef, Error> {
+        loop {
+            let Some(Value::List(List::Cons(head, tail))) = list.shared() else {
+                return Ok(Value::String(joined).new_ref());
+            };
+
+            let element = match head.thunk() {
+                Some(thunk) => thunk.value(),
+                None => Some(head.clone()),
+            };
+
+            let Some(element) = element else {
+                let onto = Value::Builtin(BuiltIn::JoinOnto(separator, joined, tail.clone()));
+                return self.apply(onto.new_ref(), head.clone());
+            };
 
-        let lhs = self.unwrap_lazy(lhs)?;
+            joined.push_str(&element.string_for_operator("Join")?);
 
-        let value = match &*lhs {
-            Value::Fn(body, env) => self.evaluate_fn(body.clone(), env.clone(), rhs),
-            Value::Builtin(built_in) => {
-                let rhs = self.unwrap_lazy(rhs)?;
-                self.evaluate_builtin(built_in, rhs)
-                    .map_err(|err| err.annotate(lhs_expr.token))
+            if let Some(Value::List(List::Cons(_, _))) = tail.shared() {
+                joined.push_str(&separator);
             }
-            _ => Err(Error::ValueNotCallable(lhs).annotate(lhs_expr.token)),
-        }?;
 
-        let value = self.unwrap_lazy(value)?;
+            list = tail.clone();
+        }
+    }
+
+    /// Calls the builtin with the value, once all elements of its lists and records are unwrapped
+    ///
+    /// Builtins can't unwrap values themselves. Once an element isn't unwrapped yet, a lazy call
+    /// is returned, that continues with [`BuiltIn::UnwrapFor`] after unwrapping it.
+    /// The elements are unwrapped in order, the pending ones are kept on a stack. The stack is
+    /// shared with the lazy call, so that it is never copied.
+    fn unwrap_elements(
+        &mut self,
+        function: &BuiltIn,
+        value: ValueRef,
+        pending: Arc<Mutex<Vec<ValueRef>>>,
+    ) -> Result<ValueRef, Error> {
+        let mut stack = pending.lock().unwrap();
+
+        while let Some(element) = stack.pop() {
+            let unwrapped = match element.thunk() {
+                Some(thunk) => thunk.value(),
+                None => Some(element.clone()),
+            };
+
+            let Some(unwrapped) = unwrapped else {
+                drop(stack);
+                let rest = BuiltIn::UnwrapFor(Box::new(function.clone()), value, pending);
+                return self.apply(Value::Builtin(rest).new_ref(), element);
+            };
+
+            match unwrapped.shared() {
+                Some(Value::List(list)) => {
+                    let elements: Vec<_> = list.iter().cloned().collect();
+                    stack.extend(elements.into_iter().rev());
+                }
+                Some(Value::Record(record)) => stack.extend(record.values().rev().cloned()),
+                _ => {}
+            }
+        }
 
-        Ok(cell.get_or_init(|| value).clone())
+        drop(stack);
+
+        self.evaluate_unwrapped(function, value)
     }
 
-    fn evaluate_builtin(&mut self, function: &BuiltIn, rhs: ValueRef) -> Result<ValueRef, Error> {
+    /// Calls a builtin, that shows or compares the value and needs all of its elements unwrapped
+    fn evaluate_unwrapped(&mut self, function: &BuiltIn, rhs: ValueRef) -> Result<ValueRef, Error> {
         match function {
             BuiltIn::Print => {
                 let mut output = self.output.lock().unwrap();
 
-                match rhs.as_ref() {
-                    Value::Number(n) => write!(output, "{n}"),
-                    Value::String(s) => write!(output, "{s}"),
-                    Value::Unit => write!(output, "Unit"),
-                    Value::LazyCall(_, _, _, _) => panic!("Lazy passed to print"),
-                    Value::Fn(_, _) => write!(output, "Function"),
-                    Value::Builtin(_) => write!(output, "Builtin Function"),
+                match &rhs {
+                    ValueRef::Number(n) => write!(output, "{n}"),
+                    ValueRef::Partial(_) => write!(output, "Builtin Function"),
+                    ValueRef::Shared(value) => match value.as_ref() {
+                        Value::String(s) => write!(output, "{s}"),
+                        Value::Unit => write!(output, "Unit"),
+                        Value::LazyCall(_) => panic!("Lazy passed to print"),
+                        Value::Fn(_, _) => write!(output, "Function"),
+                        Value::Builtin(_) => write!(output, "Builtin Function"),
+                        Value::List(_) | Value::Record(_) => write!(output, "{rhs}"),
+                    },
                 }
                 .map_err(|_| Error::OutputNotWritable)?;
 
@@ -108,77 +785,44 @@ impl<'a> Interpreter<'a> {
             BuiltIn::PrintLn => {
                 let mut output = self.output.lock().unwrap();
 
-                match rhs.as_ref() {
-                    Value::Number(n) => writeln!(output, "{n}"),
-                    Value::String(s) => writeln!(output, "{s}"),
-                    Value::Unit => writeln!(output, "Unit"),
-                    Value::LazyCall(_, _, _, _) => panic!("Lazy passed to print"),
-                    Value::Fn(_, _) => writeln!(output, "Function"),
-                    Value::Builtin(_) => writeln!(output, "Builtin Function"),
+                match &rhs {
+                    ValueRef::Number(n) => writeln!(output, "{n}"),
+                    ValueRef::Partial(_) => writeln!(output, "Builtin Function"),
+                    ValueRef::Shared(value) => match value.as_ref() {
+                        Value::String(s) => writeln!(output, "{s}"),
+                        Value::Unit => writeln!(output, "Unit"),
+                        Value::LazyCall(_) => panic!("Lazy passed to print"),
+                        Value::Fn(_, _) => writeln!(output, "Function"),
+                        Value::Builtin(_) => writeln!(output, "Builtin Function"),
+                        Value::List(_) | Value::Record(_) => writeln!(output, "{rhs}"),
+                    },
                 }
                 .map_err(|_| Error::OutputNotWritable)?;
 
                 Ok(fun_val!(call!(identifier!(0), unit!())))
             }
 
-            BuiltIn::Is => Ok(Value::Builtin(BuiltIn::IsOf(rhs)).new_ref()),
-            BuiltIn::IsNot => Ok(Value::Builtin(BuiltIn::IsNotOf(rhs)).new_ref()),
-
-            BuiltIn::IsOf(lhs) => Ok(
-                if match (lhs.as_ref(), rhs.as_ref()) {
-                    (Value::Number(l0), Value::Number(r0)) => l0 == r0,
-                    (Value::String(l0), Value::String(r0)) => l0 == r0,
-                    (Value::Unit, Value::Unit) => true,
-                    _ => false,
-                } {
-                    builtin::TRUE.clone()
-                } else {
-                    builtin::FALSE.clone()
-                },
+            BuiltIn::ToString => Ok(match rhs.shared() {
+                Some(Value::String(_)) => rhs,
+                _ => Value::String(rhs.to_string()).new_ref(),
+            }),
+
+            // Numbers are compared in place, everything else is shared with the builtin
+            BuiltIn::Is => Ok(match rhs {
+                ValueRef::Number(number) => ValueRef::Partial(Partial::IsOf(number)),
+                _ => Value::Builtin(BuiltIn::IsOf(rhs)).new_ref(),
+            }),
+            BuiltIn::IsNot => Ok(match rhs {
+                ValueRef::Number(number) => ValueRef::Partial(Partial::IsNotOf(number)),
+                _ => Value::Builtin(BuiltIn::IsNotOf(rhs)).new_ref(),
+            }),
+
+            BuiltIn::IsOf(lhs) => Ok(builtin::boolean(lhs.equals(&rhs))),
+
+            BuiltIn::IsNotOf(lhs) => Ok(builtin::boolean(!lhs.equals(&rhs))),
+            _ => unreachable!(
+                "Only builtins, that show or compare their argument, unwrap its elements"
             ),
-
-            BuiltIn::IsNotOf(lhs) => Ok(
-                if match (lhs.as_ref(), rhs.as_ref()) {
-                    (Value::Number(l0), Value::Number(r0)) => l0 == r0,
-                    (Value::String(l0), Value::String(r0)) => l0 == r0,
-                    (Value::Unit, Value::Unit) => true,
-                    _ => false,
-                } {
-                    builtin::FALSE.clone()
-                } else {
-                    builtin::TRUE.clone()
-                },
-            ),
-
-            BuiltIn::Add => {
-                Ok(Value::Builtin(BuiltIn::AddOf(rhs.number_for_operator("Add")?)).new_ref())
-            }
-            BuiltIn::Sub => {
-                Ok(Value::Builtin(BuiltIn::SubOf(rhs.number_for_operator("Sub")?)).new_ref())
-            }
-            BuiltIn::Mul => {
-                Ok(Value::Builtin(BuiltIn::MulOf(rhs.number_for_operator("Mul")?)).new_ref())
-            }
-            BuiltIn::Div => {
-                Ok(Value::Builtin(BuiltIn::DivOf(rhs.number_for_operator("Div")?)).new_ref())
-            }
-
-            BuiltIn::AddOf(lhs) => {
-                Ok(Value::Number(lhs + rhs.number_for_operator("Add")?).new_ref())
-            }
-            BuiltIn::SubOf(lhs) => {
-                Ok(Value::Number(lhs - rhs.number_for_operator("Sub")?).new_ref())
-            }
-            BuiltIn::MulOf(lhs) => {
-                Ok(Value::Number(lhs * rhs.number_for_operator("Mul")?).new_ref())
-            }
-            BuiltIn::DivOf(lhs) => {
-                Ok(Value::Number(lhs / rhs.number_for_operator("Div")?).new_ref())
-            }
-            BuiltIn::Import => match rhs.as_ref() {
-                Value::String(source) => self.import(source.clone()),
-                _ => Err(Error::ArgumentToImportMustBeAString),
-            },
         }
     }
 
@@ -187,42 +831,136 @@ impl<'a> Interpreter<'a> {
             let code = std::str::from_utf8(&file.data).unwrap();
             let path = PathBuf::from(&source_file).parent().unwrap().to_path_buf();
 
-            return self.evaluate_imported(code, source_file, path);
+            return self.evaluate_imported(code, source_file.clone(), source_file, path);
         }
 
         let mut file = self.execution_path.clone();
         file.push(&source_file);
         file.set_extension("mrbl");
 
-        let Ok(code) = fs::read_to_string(file.clone()) else {
+        let (Ok(code), Ok(key)) = (fs::read_to_string(&file), fs::canonicalize(&file)) else {
             return Err(Error::ImportCouldNotBeResolved(source_file));
         };
 
-        self.evaluate_imported(&code, source_file, file.parent().unwrap().to_path_buf())
+        self.evaluate_imported(
+            &code,
+            key.to_string_lossy().into_owned(),
+            source_file,
+            file.parent().unwrap().to_path_buf(),
+        )
     }
 
     fn evaluate_imported(
         &mut self,
         code: &str,
+        key: String,
         source_file: String,
         file_path: PathBuf,
     ) -> Result<ValueRef, Error> {
-        let source = Source::new(code);
+        if let Some(value) = self.modules.cache.get(&key) {
+            return Ok(value.clone());
+        }
+
+        if self
+            .modules
+            .loading
+            .iter()
+            .any(|(loading, _)| *loading == key)
+        {
+            let mut chain = self
+                .modules
+                .loading
+                .iter()
+                .skip_while(|(loading, _)| *loading != key)
+                .map(|(_, name)| name.clone())
+                .collect::<Vec<_>>();
+            chain.push(source_file);
+
+            return Err(Error::CyclicImport(chain));
+        }
+
+        self.modules.files.push(
+            SourceFile {
+                name: source_file.clone(),
+                code: code.to_string(),
+            }
+            .into(),
+        );
+        let source = Source::in_file(code, self.modules.files.len());
+
+        // The imported module shares the modules of this run, so they are only evaluated once
+        let mut interpreter = Interpreter {
+            execution_path: file_path,
+            input: self.input.clone(),
+            output: self.output.clone(),
+            modules: mem::take(&mut self.modules),
+            backend: self.backend,
+            optimize: self.optimize,
+            budget: mem::take(&mut self.budget),
+            compiled: HashMap::new(),
+        };
 
-        evaluate_code(code, self._input.clone(), self.output.clone(), file_path).map_err(
-            move |err| {
+        interpreter
+            .modules
+            .loading
+            .push((key.clone(), source_file.clone()));
+
+        let options = EvalOptions {
+            optimize: self.optimize,
+            ..Default::default()
+        };
+        let result = compile_with(&source, &options).and_then(|expr| interpreter.interpret(expr));
+
+        self.modules = interpreter.modules;
+        self.modules.loading.pop();
+        self.budget = interpreter.budget;
+
+        let value = result.map_err(|err| match err.error {
+            // The limits apply to the whole run, so exceeding them isn't specific to the module
+            Error::StepLimitExceeded(_)
+            | Error::ThunkLimitExceeded(_)
+            | Error::TimeLimitExceeded(_) => err.error,
+            _ => {
                 let err_string = err.of_source(&source);
 
                 Error::ErrorInImportedFile(source_file, err_string)
-            },
-        )
+            }
+        })?;
+
+        self.modules.cache.insert(key, value.clone());
+
+        Ok(value)
     }
 
     pub fn new(input: Input<'a>, output: Output<'a>, path: PathBuf) -> Self {
         Self {
-            _input: input,
+            input,
             output,
             execution_path: path,
+            modules: Modules::default(),
+            backend: Backend::default(),
+            optimize: false,
+            budget: Budget::default(),
+            compiled: HashMap::new(),
         }
     }
+
+    pub fn with_backend(&mut self, backend: Backend) -> &Self {
+        self.backend = backend;
+
+        self
+    }
+
+    pub fn with_limits(&mut self, limits: Limits) -> &Self {
+        self.budget = Budget::new(limits);
+
+        self
+    }
+
+    /// Compiles imported files with the optimizing pass, see [`crate::optimizer`]
+    pub fn with_optimization(&mut self, optimize: bool) -> &Self {
+        self.optimize = optimize;
+
+        self
+    }
 }
@@ -8,23 +8,32 @@ use std::{
 
 use compiler::Compiler;
 use error::AnnotatedError;
-use interpreter::{Input, Interpreter, Output, ValueResult};
+use expr::ExprRef;
+use interpreter::{Backend, Input, Interpreter, Output, ValueResult};
+use limits::Limits;
 use scanner::Scanner;
 use source::Source;
 use value::ValueRef;
 
 pub mod builtin;
+pub mod bytecode;
+pub mod closure;
 pub mod compiler;
+pub mod diagnostic;
 pub mod environment;
 pub mod error;
 pub mod expr;
 pub mod identifier;
 pub mod interpreter;
+pub mod limits;
 pub mod meta;
+pub mod optimizer;
 pub mod scanner;
 pub mod source;
+pub mod strictness;
 pub mod token;
 pub mod value;
+pub mod vm;
 
 #[cfg(test)]
 pub mod tests;
@@ -63,6 +72,27 @@ pub fn execute_string(code: &str, execution_path: PathBuf) -> OutputResult {
     .map(move |val| (val, String::from_utf8(output).unwrap()))
 }
 
+/// Executes the code like [`execute_string`], aborting once any of the limits is exceeded
+pub fn execute_string_with_limits(
+    code: &str,
+    execution_path: PathBuf,
+    limits: Limits,
+) -> OutputResult {
+    let mut output = Vec::new();
+    let cursor = Cursor::new(&mut output);
+    evaluate_code_with(
+        code,
+        Arc::new(Mutex::new(Box::new(stdin()))),
+        Arc::new(Mutex::new(Box::new(cursor))),
+        execution_path,
+        EvalOptions {
+            limits,
+            ..Default::default()
+        },
+    )
+    .map(move |val| (val, String::from_utf8(output).unwrap()))
+}
+
 pub fn execute_file_at(file: &str) -> OutputResult {
     let mut output = Vec::new();
     let cursor = Cursor::new(&mut output);
@@ -76,19 +106,75 @@ pub fn execute_file_at(file: &str) -> OutputResult {
     .map(move |val| (val, String::from_utf8(output).unwrap()))
 }
 
+/// Compiles the source with the default bindings
+pub fn compile(source: &Source) -> Result<ExprRef, AnnotatedError> {
+    compile_with(source, &EvalOptions::default())
+}
+
+/// Compiles the source like [`compile`], optimizing the expression, if enabled in the options
+pub fn compile_with(source: &Source, options: &EvalOptions) -> Result<ExprRef, AnnotatedError> {
+    let scanner = Scanner::new(source);
+
+    let mut compiler = Compiler::new(source, scanner);
+    compiler.with_bindings(Compiler::default_bindings());
+    compiler.with_optimization(options.optimize);
+    compiler.compile()
+}
+
+/// The options of a single run of a marble program
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct EvalOptions {
+    /// The way the program is executed
+    pub backend: Backend,
+    /// The resources the run may use
+    pub limits: Limits,
+    /// Whether the program and the files it imports are optimized, see [`optimizer`]
+    pub optimize: bool,
+}
+
 pub fn evaluate_code<'a>(
     code: &str,
     input: Input<'a>,
     output: Output<'a>,
     execution_path: PathBuf,
 ) -> ValueResult {
-    let source = Source::new(code);
-    let scanner = Scanner::new(&source);
+    evaluate_code_with(code, input, output, execution_path, EvalOptions::default())
+}
 
-    let mut compiler = Compiler::new(&source, scanner);
-    compiler.with_bindings(Compiler::default_bindings());
-    let expr = compiler.compile()?;
+/// Evaluates the code like [`evaluate_code`] with the options
+pub fn evaluate_code_with<'a>(
+    code: &str,
+    input: Input<'a>,
+    output: Output<'a>,
+    execution_path: PathBuf,
+    options: EvalOptions,
+) -> ValueResult {
+    let source = Source::new(code);
+    let expr = compile_with(&source, &options)?;
 
     let mut interpreter = Interpreter::new(input, output, execution_path);
+    interpreter.with_backend(options.backend);
+    interpreter.with_limits(options.limits);
+    interpreter.with_optimization(options.optimize);
     interpreter.interpret(expr)
 }
+
+#[deprecated(note = "use `evaluate_code_with` and set the backend in the `EvalOptions`")]
+pub fn evaluate_code_with_backend<'a>(
+    code: &str,
+    input: Input<'a>,
+    output: Output<'a>,
+    execution_path: PathBuf,
+    backend: Backend,
+) -> ValueResult {
+    evaluate_code_with(
+        code,
+        input,
+        output,
+        execution_path,
+        EvalOptions {
+            backend,
+            ..Default::default()
+        },
+    )
+}
@@ -0,0 +1,97 @@
+//! Bounds on the resources a single run may use
+//!
+//! A marble program may never terminate, so hosts evaluating untrusted code can limit
+//! the number of evaluation steps, the number of lazy calls alive at the same time and
+//! the wall-clock time of a run. Exceeding a limit aborts the run with an error.
+
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{environment::EnvRef, error::Error, expr::ExprRef, value::Thunk};
+
+/// The elapsed time is only checked every so many steps, as getting the time is comparatively slow
+const TIME_CHECK_INTERVAL: u64 = 1024;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Limits {
+    /// The maximum number of evaluation steps
+    pub steps: Option<u64>,
+    /// The maximum number of lazy calls alive at the same time
+    pub thunks: Option<usize>,
+    /// The maximum wall-clock time of a run
+    ///
+    /// Note that the time isn't available on every platform, e.g. on `wasm32-unknown-unknown`
+    pub time: Option<Duration>,
+}
+
+/// Keeps track of the resources used by a run
+#[derive(Debug, Default)]
+pub(crate) struct Budget {
+    limits: Limits,
+    steps: u64,
+    started: Option<Instant>,
+    thunks: Arc<AtomicUsize>,
+}
+
+/// Counts a lazy call as alive, until it is dropped
+#[derive(Debug)]
+pub struct LiveThunk(Arc<AtomicUsize>);
+
+impl Drop for LiveThunk {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Budget {
+    pub fn new(limits: Limits) -> Self {
+        Self {
+            limits,
+            ..Default::default()
+        }
+    }
+
+    /// Counts an evaluation step and checks the step and time limits
+    pub fn step(&mut self) -> Result<(), Error> {
+        self.steps += 1;
+
+        if let Some(limit) = self.limits.steps
+            && self.steps > limit
+        {
+            return Err(Error::StepLimitExceeded(limit));
+        }
+
+        if let Some(limit) = self.limits.time {
+            let started = *self.started.get_or_insert_with(Instant::now);
+
+            if self.steps.is_multiple_of(TIME_CHECK_INTERVAL) && started.elapsed() > limit {
+                return Err(Error::TimeLimitExceeded(limit));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Creates a lazy call and checks the limit of lazy calls alive at the same time
+    pub fn thunk(&self, call: ExprRef, environment: EnvRef) -> Result<Thunk, Error> {
+        let thunk = Thunk::new(call, environment);
+
+        let Some(limit) = self.limits.thunks else {
+            return Ok(thunk);
+        };
+
+        let live = self.thunks.fetch_add(1, Ordering::Relaxed) + 1;
+        let thunk = thunk.counted(LiveThunk(self.thunks.clone()));
+
+        if live > limit {
+            return Err(Error::ThunkLimitExceeded(limit));
+        }
+
+        Ok(thunk)
+    }
+}
@@ -1,14 +1,14 @@
 #[macro_export]
 macro_rules! fun {
     ($body: expr) => {
-        Expr::Fn($body).default_ref()
+        Expr::Fn($body, Vec::new()).default_ref()
     };
 }
 
 #[macro_export]
 macro_rules! fun_val {
     ($body: expr) => {
-        Value::Fn($body, Environment::root()).new_ref()
+        Value::Fn($crate::closure::convert(&$body, 1), Environment::root()).new_ref()
     };
 }
 
@@ -0,0 +1,345 @@
+//! The numbers of marble programs and the arithmetic on them
+//!
+//! Whole numbers are integers of arbitrary size, so that arithmetic on them is exact. A number
+//! only becomes a float, once a fraction is involved.
+//!
+//! ```rust
+//! use marble::number::Number;
+//! assert_eq!(&Number::Integer(10) / &Number::Integer(5), Number::Integer(2));
+//! assert_eq!(&Number::Integer(10) / &Number::Integer(4), Number::Float(2.5));
+//! ```
+
+use std::{
+    cmp::Ordering,
+    fmt::Display,
+    ops::{Add, Div, Mul, Sub},
+    str::FromStr,
+    sync::Arc,
+};
+
+use num_bigint::BigInt;
+use num_traits::{Euclid, FromPrimitive, Signed, ToPrimitive, Zero};
+
+use super::{deserialize, serialize};
+
+/// The size of the largest power, that is computed exactly
+///
+/// Computing a power can't be interrupted, so a single `Pow` mustn't be able to take
+/// longer than the limits of a run allow.
+pub const MAX_POWER_BITS: u64 = 1 << 16;
+
+#[derive(Debug, Clone)]
+pub enum Number {
+    Integer(i64),
+    /// An integer, that doesn't fit into an i64
+    ///
+    /// Integers are only stored like this, if they have to be, so that the arithmetic on
+    /// most numbers doesn't need to allocate. The digits are shared, so copying a number is cheap.
+    Big(Arc<BigInt>),
+    Float(f64),
+}
+
+impl Number {
+    /// Parses a number from its written form, like ```MinusFortyTwo``` or ```ThreePointOneFour```
+    pub fn from_words(word: &str) -> Option<Number> {
+        deserialize::parse_integer(word)
+            .map(Number::from)
+            .or_else(|| deserialize::parse_fraction(word).map(Number::Float))
+    }
+
+    /// Parses a number from its written form as an ordinal, like ```TwentyFirst```
+    pub fn from_ordinal_words(word: &str) -> Option<Number> {
+        deserialize::parse_ordinal(word).map(Number::from)
+    }
+
+    /// Parses a number from its digits, like ```-42``` or ```3.14```
+    pub fn from_digits(digits: &str) -> Option<Number> {
+        BigInt::from_str(digits).map(Number::from).ok().or_else(|| {
+            digits
+                .parse::<f64>()
+                .ok()
+                .filter(|float| float.is_finite())
+                .map(Number::Float)
+        })
+    }
+
+    /// Converts the number to its written form
+    ///
+    /// Fails for infinite numbers and numbers larger than the largest factor, that has a name
+    pub fn to_words(&self) -> Option<String> {
+        match self {
+            Number::Integer(integer) => Some(serialize::display_integer(*integer)),
+            Number::Big(big) => serialize::display_big_integer(big),
+            // Floats this large don't have a fraction, but can't be converted to an u64
+            Number::Float(float) if float.abs() >= u64::MAX as f64 => {
+                serialize::display_big_integer(&BigInt::from_f64(*float)?)
+            }
+            Number::Float(float) => float
+                .is_finite()
+                .then(|| serialize::display_fraction(*float)),
+        }
+    }
+
+    pub fn as_f64(&self) -> f64 {
+        match self {
+            Number::Integer(integer) => *integer as f64,
+            Number::Big(big) => big.to_f64().unwrap_or(f64::NAN),
+            Number::Float(float) => *float,
+        }
+    }
+
+    /// Returns the number as an index, if it is whole and not negative
+    pub fn as_index(&self) -> Option<usize> {
+        match self {
+            Number::Integer(integer) => usize::try_from(*integer).ok(),
+            Number::Big(_) => None,
+            Number::Float(float) => (float.fract() == 0.0
+                && (0.0..usize::MAX as f64).contains(float))
+            .then_some(*float as usize),
+        }
+    }
+
+    pub fn is_finite(&self) -> bool {
+        match self {
+            Number::Integer(_) | Number::Big(_) => true,
+            Number::Float(float) => float.is_finite(),
+        }
+    }
+
+    /// The remainder of the division, which is never negative
+    pub fn rem_euclid(&self, rhs: &Number) -> Number {
+        self.apply(
+            rhs,
+            i64::checked_rem_euclid,
+            |lhs, rhs| (!rhs.is_zero()).then(|| lhs.rem_euclid(rhs)),
+            f64::rem_euclid,
+        )
+    }
+
+    /// Raises the number to the power
+    ///
+    /// A power, that would have more than [`MAX_POWER_BITS`] bits, isn't computed exactly,
+    /// but as a float, which is infinite for most of them.
+    pub fn pow(&self, rhs: &Number) -> Number {
+        self.apply(
+            rhs,
+            |lhs, rhs| lhs.checked_pow(u32::try_from(rhs).ok()?),
+            |lhs, rhs| {
+                let exponent = rhs.to_u32()?;
+
+                // The power has at least this many bits, except for the leading one
+                let bits = lhs.bits().saturating_sub(1).saturating_mul(exponent.into());
+                (bits <= MAX_POWER_BITS).then(|| lhs.pow(exponent))
+            },
+            f64::powf,
+        )
+    }
+
+    pub fn min(&self, rhs: &Number) -> Number {
+        if rhs < self {
+            rhs.clone()
+        } else {
+            self.clone()
+        }
+    }
+
+    pub fn max(&self, rhs: &Number) -> Number {
+        if rhs > self {
+            rhs.clone()
+        } else {
+            self.clone()
+        }
+    }
+
+    pub fn floor(&self) -> Number {
+        self.round_with(f64::floor)
+    }
+
+    pub fn ceil(&self) -> Number {
+        self.round_with(f64::ceil)
+    }
+
+    pub fn round(&self) -> Number {
+        self.round_with(f64::round)
+    }
+
+    pub fn abs(&self) -> Number {
+        match self {
+            Number::Integer(integer) => integer
+                .checked_abs()
+                .map_or_else(|| BigInt::from(*integer).abs().into(), Number::Integer),
+            Number::Big(big) => big.abs().into(),
+            Number::Float(float) => Number::Float(float.abs()),
+        }
+    }
+
+    /// The square root, which is exact for square numbers
+    pub fn sqrt(&self) -> Number {
+        let root = self.as_f64().sqrt();
+
+        match self {
+            Number::Integer(integer) if root.is_finite() => {
+                let whole = root.round() as i64;
+
+                if whole.checked_mul(whole) == Some(*integer) {
+                    Number::Integer(whole)
+                } else {
+                    Number::Float(root)
+                }
+            }
+            Number::Big(big) if big.is_positive() => {
+                let whole = big.sqrt();
+
+                if &(&whole * &whole) == big.as_ref() {
+                    whole.into()
+                } else {
+                    Number::Float(root)
+                }
+            }
+            _ => Number::Float(root),
+        }
+    }
+
+    fn to_big(&self) -> Option<BigInt> {
+        match self {
+            Number::Integer(integer) => Some(BigInt::from(*integer)),
+            Number::Big(big) => Some(big.as_ref().clone()),
+            Number::Float(_) => None,
+        }
+    }
+
+    /// Applies the operation for integers, big integers or floats, depending on the numbers
+    ///
+    /// Big integers are only used, if the operation on integers overflows. Floats are used,
+    /// if either number is a float or the result isn't a whole number.
+    fn apply(
+        &self,
+        rhs: &Number,
+        integer: impl Fn(i64, i64) -> Option<i64>,
+        big: impl Fn(&BigInt, &BigInt) -> Option<BigInt>,
+        float: impl Fn(f64, f64) -> f64,
+    ) -> Number {
+        if let (Number::Integer(lhs), Number::Integer(rhs)) = (self, rhs)
+            && let Some(result) = integer(*lhs, *rhs)
+        {
+            return Number::Integer(result);
+        }
+
+        if let (Some(lhs), Some(rhs)) = (self.to_big(), rhs.to_big())
+            && let Some(result) = big(&lhs, &rhs)
+        {
+            return result.into();
+        }
+
+        Number::Float(float(self.as_f64(), rhs.as_f64()))
+    }
+
+    /// Rounds floats to integers, unless they are infinite
+    fn round_with(&self, round: impl Fn(f64) -> f64) -> Number {
+        match self {
+            Number::Integer(_) | Number::Big(_) => self.clone(),
+            Number::Float(float) => {
+                let rounded = round(*float);
+
+                if (i64::MIN as f64..i64::MAX as f64).contains(&rounded) {
+                    Number::Integer(rounded as i64)
+                } else {
+                    BigInt::from_f64(rounded).map_or(Number::Float(rounded), Number::from)
+                }
+            }
+        }
+    }
+}
+
+impl From<BigInt> for Number {
+    fn from(big: BigInt) -> Number {
+        big.to_i64()
+            .map_or_else(|| Number::Big(Arc::new(big)), Number::Integer)
+    }
+}
+
+impl Add for &Number {
+    type Output = Number;
+
+    fn add(self, rhs: &Number) -> Number {
+        self.apply(
+            rhs,
+            i64::checked_add,
+            |lhs, rhs| Some(lhs + rhs),
+            |lhs, rhs| lhs + rhs,
+        )
+    }
+}
+
+impl Sub for &Number {
+    type Output = Number;
+
+    fn sub(self, rhs: &Number) -> Number {
+        self.apply(
+            rhs,
+            i64::checked_sub,
+            |lhs, rhs| Some(lhs - rhs),
+            |lhs, rhs| lhs - rhs,
+        )
+    }
+}
+
+impl Mul for &Number {
+    type Output = Number;
+
+    fn mul(self, rhs: &Number) -> Number {
+        self.apply(
+            rhs,
+            i64::checked_mul,
+            |lhs, rhs| Some(lhs * rhs),
+            |lhs, rhs| lhs * rhs,
+        )
+    }
+}
+
+impl Div for &Number {
+    type Output = Number;
+
+    /// Divides exactly, if the divisor is a factor. Dividing by zero results in a float
+    fn div(self, rhs: &Number) -> Number {
+        self.apply(
+            rhs,
+            |lhs, rhs| {
+                lhs.checked_rem(rhs)
+                    .filter(|remainder| *remainder == 0)
+                    .and_then(|_| lhs.checked_div(rhs))
+            },
+            |lhs, rhs| (!rhs.is_zero() && (lhs % rhs).is_zero()).then(|| lhs / rhs),
+            |lhs, rhs| lhs / rhs,
+        )
+    }
+}
+
+/// Numbers are equal, if they have the same value, regardless of whether they are integers or floats
+impl PartialEq for Number {
+    fn eq(&self, other: &Number) -> bool {
+        self.partial_cmp(other) == Some(Ordering::Equal)
+    }
+}
+
+impl PartialOrd for Number {
+    fn partial_cmp(&self, other: &Number) -> Option<Ordering> {
+        match (self, other) {
+            (Number::Integer(lhs), Number::Integer(rhs)) => Some(lhs.cmp(rhs)),
+            (Number::Float(_), _) | (_, Number::Float(_)) => {
+                self.as_f64().partial_cmp(&other.as_f64())
+            }
+            _ => Some(self.to_big()?.cmp(&other.to_big()?)),
+        }
+    }
+}
+
+impl Display for Number {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Number::Integer(integer) => f.write_fmt(format_args!("{integer}")),
+            Number::Big(big) => f.write_fmt(format_args!("{big}")),
+            Number::Float(float) => f.write_fmt(format_args!("{float}")),
+        }
+    }
+}
@@ -1,3 +1,8 @@
+use num_bigint::BigInt;
+use num_traits::{ToPrimitive, Zero};
+
+use super::serialize::FACTOR_NAMES;
+
 /// Peeks the next part (digit, factor, etc.)
 ///
 /// A part ends at the end of the string or before the next capitalized letter
@@ -10,7 +15,7 @@ fn peek_next_part(word: &str) -> &str {
 
     word[start..]
         .find(|c: char| c.is_ascii_uppercase())
-        .map_or_else(|| (word), |u| &word[..start + u])
+        .map_or_else(|| word, |u| &word[..start + u])
 }
 
 fn first_char_length(word: &str) -> usize {
@@ -57,43 +62,22 @@ pub fn parse_digit(digit: &str) -> Option<u64> {
     }
 }
 
-/// Converts a factor to its number
-///
-/// If the provided word is a factor, then this function returns its numeral value
-pub fn parse_factor(word: &str) -> Option<u64> {
-    match word {
-        "" => Some(1),
-        "Thousand" => Some(1_000),
-        "Million" => Some(1_000_000),
-        "Billion" => Some(1_000_000_000),
-        "Trillion" => Some(1_000_000_000_000),
-        "Quadrillion" => Some(1_000_000_000_000_000),
-        "Quintillion" => Some(1_000_000_000_000_000_000),
-        _ => None,
-    }
+/// Converts a factor to its power of a thousand
+///
+/// If the provided word is a factor, then this function returns n, where the factor is 1000 ** n
+pub fn parse_factor(word: &str) -> Option<usize> {
+    FACTOR_NAMES.iter().position(|name| *name == word)
 }
 
-/// Splits off a triplet of digits from the word and tries to parse it
+/// Splits off a number below one hundred from the word and tries to parse it
 ///
 /// Then returns the parsed number and the remaining string if successfull
-pub fn parse_triplet(mut word: &str) -> Option<(u64, &str)> {
-    let mut number = 0;
-
+fn parse_below_hundred(mut word: &str) -> Option<(u64, &str)> {
     let mut next_part = peek_next_part(word);
 
     if let Some(digit) = parse_digit(next_part) {
-        // Consume the digit
-        (next_part, word) = consume_and_peek(word);
-
-        if next_part != "Hundred" {
-            // Its a number between 0 and 9
-            return Some((digit, word));
-        }
-
-        // Consume "Hundred"
-        (next_part, word) = consume_and_peek(word);
-
-        number += digit * 100;
+        (_, word) = split_next_part(word);
+        return Some((digit, word));
     }
 
     if let Some(literal) = match next_part {
@@ -112,68 +96,106 @@ pub fn parse_triplet(mut word: &str) -> Option<(u64, &str)> {
         (_, word) = split_next_part(word);
 
         // After matching a literal, nothing can follow this
-        return Some((number + literal, word));
+        return Some((literal, word));
     }
 
-    if let Some(dec) = match next_part {
-        "Twenty" => Some(20),
-        "Thirty" => Some(30),
-        "Forty" => Some(40),
-        "Fifty" => Some(50),
-        "Sixty" => Some(60),
-        "Seventy" => Some(70),
-        "Eighty" => Some(80),
-        "Ninety" => Some(90),
-        _ => None,
-    } {
-        (next_part, word) = consume_and_peek(word);
-        number += dec;
+    let dec = match next_part {
+        "Twenty" => 20,
+        "Thirty" => 30,
+        "Forty" => 40,
+        "Fifty" => 50,
+        "Sixty" => 60,
+        "Seventy" => 70,
+        "Eighty" => 80,
+        "Ninety" => 90,
+        _ => return None,
+    };
+
+    (next_part, word) = consume_and_peek(word);
+
+    match parse_digit(next_part) {
+        Some(digit) => Some((dec + digit, split_next_part(word).1)),
+        None => Some((dec, word)),
     }
+}
 
-    if let Some(digit) = parse_digit(next_part) {
-        (_, word) = split_next_part(word);
-        number += digit;
+/// Splits off a triplet of digits from the word and tries to parse it
+///
+/// The hundreds may also be counted up to 99, like in ```FifteenHundred```, so the parsed
+/// number can be larger than a triplet. Then returns the parsed number and the remaining
+/// string if successfull
+pub fn parse_triplet(word: &str) -> Option<(u64, &str)> {
+    let (number, mut word) = parse_below_hundred(word)?;
+
+    // ZeroHundred isn't a number and TenHundred would be OneThousand
+    if peek_next_part(word) != "Hundred" || number == 0 || (number >= 10 && number % 10 == 0) {
+        return Some((number, word));
     }
 
-    // If nothing was matched, we return None
-    if number == 0 {
-        return None;
+    let hundreds = number * 100;
+
+    // Consume "Hundred"
+    let next_part;
+    (next_part, word) = consume_and_peek(word);
+
+    // An "And" has to be followed by the rest of the triplet, like in OneHundredAndFive
+    if next_part == "And" {
+        let (rest, word) = parse_below_hundred(split_next_part(word).1)?;
+        return Some((hundreds + rest, word));
     }
 
-    Some((number, word))
+    match parse_below_hundred(word) {
+        Some((rest, word)) => Some((hundreds + rest, word)),
+        None => Some((hundreds, word)),
+    }
 }
 
 /// Tries to parse an integer from its written form
 ///
 /// ```rust
 /// use marble::number::deserialize;
-/// assert_eq!(deserialize::parse_number("OneHundredTwentyThree"), Some(123));
+/// use num_bigint::BigInt;
+///
+/// assert_eq!(deserialize::parse_number("OneHundredTwentyThree"), Some(BigInt::from(123)));
+/// assert_eq!(deserialize::parse_number("TwelveHundredAndFive"), Some(BigInt::from(1205)));
 /// ```
 ///
 /// This is an operation that can fail, as not every word is a valid number. Thus it returns an Option
-pub fn parse_number(mut word: &str) -> Option<u64> {
-    let mut smallest_factor = u64::MAX;
-    let mut number = 0;
+pub fn parse_number(mut word: &str) -> Option<BigInt> {
+    let mut smallest_factor = FACTOR_NAMES.len();
+    let mut number = BigInt::ZERO;
 
     loop {
         // First parse a triplet (OneHundredNineteen) and then its factor (Thousand)
-        let parsed_number;
-        (parsed_number, word) = parse_triplet(word)?;
+        let triplet;
+        (triplet, word) = parse_triplet(word)?;
         let factor_word;
         (factor_word, word) = split_next_part(word);
         let factor = parse_factor(factor_word)?;
 
         // You can't have million after thousand
-        if factor > smallest_factor {
+        if factor >= smallest_factor {
             return None;
         }
 
-        smallest_factor = factor;
+        // FifteenHundred can't be combined with factors, that's what OneThousandFiveHundred is for
+        if triplet >= 1_000 && (factor > 0 || !number.is_zero()) {
+            return None;
+        }
 
-        number += parsed_number * factor;
+        smallest_factor = factor;
+        number += BigInt::from(triplet) * BigInt::from(1_000).pow(factor as u32);
 
         // If the word is empty or the last factor was parsed, we stop
-        if factor == 1 || word.is_empty() {
+        if factor == 0 || word.is_empty() {
+            break;
+        }
+
+        // An "And" introduces the last part below one hundred, like in OneThousandAndFive
+        if peek_next_part(word) == "And" {
+            let rest;
+            (rest, word) = parse_below_hundred(split_next_part(word).1)?;
+            number += rest;
             break;
         }
     }
@@ -186,35 +208,141 @@ pub fn parse_number(mut word: &str) -> Option<u64> {
     Some(number)
 }
 
+/// Tries to parse a signed integer from its written form
+///
+/// Negative numbers are prefixed with "Minus"
+///
+/// ```rust
+/// use marble::number::deserialize;
+/// use num_bigint::BigInt;
+///
+/// assert_eq!(deserialize::parse_integer("MinusFortyTwo"), Some(BigInt::from(-42)));
+/// ```
+pub fn parse_integer(word: &str) -> Option<BigInt> {
+    match word.strip_prefix("Minus") {
+        Some(word) => parse_number(word).map(|number| -number),
+        None => parse_number(word),
+    }
+}
+
+/// Converts the last part of an ordinal to the part of its cardinal
+///
+/// If the provided word is an ordinal part like ```Third``` or ```Twentieth```, then this function
+/// returns the part it is counted by, like ```Three``` or ```Twenty```
+fn cardinal_of_ordinal(part: &str) -> Option<String> {
+    match part {
+        "First" => Some("One".to_string()),
+        "Second" => Some("Two".to_string()),
+        "Third" => Some("Three".to_string()),
+        "Fifth" => Some("Five".to_string()),
+        "Eighth" => Some("Eight".to_string()),
+        "Ninth" => Some("Nine".to_string()),
+        "Twelfth" => Some("Twelve".to_string()),
+        _ => match part.strip_suffix("ieth") {
+            Some(decade) => Some(format!("{decade}y")),
+            // The parts above and the decades have their own ordinals, so Oneth isn't one
+            None => part
+                .strip_suffix("th")
+                .filter(|cardinal| {
+                    !matches!(
+                        *cardinal,
+                        "One" | "Two" | "Three" | "Five" | "Eight" | "Nine" | "Twelve"
+                    ) && !cardinal.ends_with('y')
+                })
+                .map(str::to_string),
+        },
+    }
+}
+
+/// Tries to parse an ordinal from its written form
+///
+/// Only the last part of an ordinal differs from the cardinal number, like in ```TwentyFirst```
+///
+/// ```rust
+/// use marble::number::deserialize;
+/// use num_bigint::BigInt;
+///
+/// assert_eq!(deserialize::parse_ordinal("TwentyFirst"), Some(BigInt::from(21)));
+/// assert_eq!(deserialize::parse_ordinal("OneHundredth"), Some(BigInt::from(100)));
+/// ```
+///
+/// This is an operation that can fail, as not every word is a valid ordinal. Thus it returns an Option
+pub fn parse_ordinal(word: &str) -> Option<BigInt> {
+    let split = word.rfind(|c: char| c.is_ascii_uppercase())?;
+    let (whole, last) = word.split_at(split);
+
+    parse_number(&format!("{whole}{}", cardinal_of_ordinal(last)?))
+}
+
 /// Tries to parse a double from its written form
 ///
-/// The decimal seperator is "Point"
+/// The decimal seperator is "Point", negative numbers are prefixed with "Minus".
+/// Halves and quarters can also be written like ```TwoAndAHalf``` or ```ThreeQuarters```
 ///
 /// ```rust
 /// use marble::number::deserialize;
 /// assert_eq!(deserialize::parse_fraction("ThreePointOneFour"), Some(3.14));
+/// assert_eq!(deserialize::parse_fraction("MinusOnePointFive"), Some(-1.5));
+/// assert_eq!(deserialize::parse_fraction("TwoAndAHalf"), Some(2.5));
 /// ```
 ///
 /// This is an operation that can fail, as not every word is a valid number. Thus it returns an Option
 pub fn parse_fraction(word: &str) -> Option<f64> {
+    match word.strip_prefix("Minus") {
+        Some(word) => parse_positive_fraction(word).map(|number| -number),
+        None => parse_positive_fraction(word),
+    }
+}
+
+/// Parses a fraction without a sign
+fn parse_positive_fraction(word: &str) -> Option<f64> {
+    if let Some(fraction) = parse_common_fraction(word) {
+        return Some(fraction);
+    }
+
     word.split_once("Point").map_or_else(
         // If there was no decimal seperator, we can just parse a number and convert to a double
-        || parse_number(word).map(|u| u as f64),
+        || parse_number(word)?.to_f64(),
         // If there is a decimal seperator, we first parse the whole number and then the fraction
         |(whole_word, mut fraction_word)| {
             parse_number(whole_word).and_then(|whole| {
-                let mut factor = 0.1;
-                let mut fraction = 0.0;
+                // The digits are collected and parsed at once, as adding up the digits one by one
+                // would accumulate rounding errors
+                let mut digits = format!("{whole}.");
                 while !fraction_word.is_empty() {
                     let digit;
                     // As long there are parts, we assume they're digits
                     (digit, fraction_word) = split_next_part(fraction_word);
-                    fraction += parse_digit(digit)? as f64 * factor;
-                    factor *= 0.1;
+                    digits.push_str(&parse_digit(digit)?.to_string());
                 }
 
-                Some(whole as f64 + fraction)
+                digits.parse().ok()
             })
         },
     )
 }
+
+/// Parses a half or quarters, optionally preceded by a whole number, like ```OneAndThreeQuarters```
+fn parse_common_fraction(word: &str) -> Option<f64> {
+    let (whole, fraction) = match word.rsplit_once("And") {
+        Some((whole, fraction)) => (Some(parse_number(whole)?), fraction),
+        None => (None, word),
+    };
+
+    let fraction = match fraction {
+        "OneHalf" => 0.5,
+        "OneQuarter" => 0.25,
+        "ThreeQuarters" => 0.75,
+        // On their own, a half and a quarter don't need to be counted
+        "Half" | "Quarter" if whole.is_some() => return None,
+        "Half" => 0.5,
+        "Quarter" => 0.25,
+        // After a whole number, it's TwoAndAHalf
+        "AHalf" | "AQuarter" if whole.is_none() => return None,
+        "AHalf" => 0.5,
+        "AQuarter" => 0.25,
+        _ => return None,
+    };
+
+    Some(whole.map_or(Some(0.0), |whole| whole.to_f64())? + fraction)
+}
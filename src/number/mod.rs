@@ -10,26 +10,33 @@
 //!
 //! ```rust
 //! use marble::number::deserialize;
-//! assert_eq!(deserialize::parse_number("OneHundredTwentyThree"), Some(123));
+//! use num_bigint::BigInt;
+//!
+//! assert_eq!(deserialize::parse_number("OneHundredTwentyThree"), Some(BigInt::from(123)));
 //! ```
 //!
 //! Please note, that serialization will always return a string directly,
 //! because all numbers can be converted to a word. However in contrast, desirialization
 //! returns an Option, as it may fail. *```"Banana"``` isn't a number after all*.
 
+pub mod arithmetic;
 pub mod deserialize;
 pub mod serialize;
 
+pub use arithmetic::Number;
+
 #[cfg(test)]
 mod test {
-    use super::{deserialize, serialize};
+    use num_bigint::BigInt;
+
+    use super::{Number, deserialize, serialize};
 
     #[test]
     pub fn convert_u64() {
         let mut i = 0;
         while i <= 100_000 {
             assert_eq!(
-                i,
+                BigInt::from(i),
                 deserialize::parse_number(&serialize::display_number(i))
                     .unwrap_or_else(|| panic!("{i} didnt convert"))
             );
@@ -50,6 +57,157 @@ mod test {
         }
     }
 
+    #[test]
+    pub fn convert_negative() {
+        for i in [-1, -42, -1_000_001, i64::MIN, i64::MAX] {
+            assert_eq!(
+                Some(BigInt::from(i)),
+                deserialize::parse_integer(&serialize::display_integer(i))
+            );
+        }
+
+        let mut i = -1_000.0;
+        while i <= 0.0 {
+            assert_eq!(
+                Some(i),
+                deserialize::parse_fraction(&serialize::display_fraction(i))
+            );
+            i += 0.25;
+        }
+    }
+
+    #[test]
+    pub fn convert_big() {
+        let mut big = BigInt::from(1);
+        for _ in 0..36 {
+            let word = serialize::display_big_integer(&big).unwrap();
+            assert_eq!(Some(&big), deserialize::parse_number(&word).as_ref());
+
+            let word = serialize::display_big_integer(&(&big * 9 + 1)).unwrap();
+            assert_eq!(Some(&big * 9 + 1), deserialize::parse_number(&word));
+
+            big *= 10;
+        }
+
+        assert_eq!(
+            Some("OneHundredDecillion".to_string()),
+            serialize::display_big_integer(&(&big / 10))
+        );
+        assert_eq!(None, serialize::display_big_integer(&big));
+    }
+
+    #[test]
+    pub fn convert_and() {
+        for i in 1..1_000_u64 {
+            let (whole, rest) = (i - i % 100, i % 100);
+            if whole == 0 || rest == 0 {
+                continue;
+            }
+
+            let word = format!(
+                "{}And{}",
+                serialize::display_number(whole),
+                serialize::display_number(rest)
+            );
+            assert_eq!(
+                deserialize::parse_number(&serialize::display_number(i)),
+                deserialize::parse_number(&word)
+            );
+            assert_eq!(
+                deserialize::parse_number(&format!("OneMillion{}", serialize::display_number(i))),
+                deserialize::parse_number(&format!("OneMillion{word}"))
+            );
+            assert_eq!(
+                Some(BigInt::from(1_000_000 + rest)),
+                deserialize::parse_number(&format!(
+                    "OneMillionAnd{}",
+                    serialize::display_number(rest)
+                ))
+            );
+        }
+    }
+
+    #[test]
+    pub fn convert_hundreds() {
+        for hundreds in 11..100_u64 {
+            if hundreds % 10 == 0 {
+                continue;
+            }
+
+            for rest in 0..100 {
+                let i = hundreds * 100 + rest;
+                let mut word = format!("{}Hundred", serialize::display_number(hundreds));
+                if rest > 0 {
+                    word.push_str(&serialize::display_number(rest));
+                }
+
+                assert_eq!(Some(BigInt::from(i)), deserialize::parse_number(&word));
+                assert_eq!(
+                    deserialize::parse_number(&serialize::display_number(i)),
+                    deserialize::parse_number(&word)
+                );
+            }
+        }
+    }
+
+    #[test]
+    pub fn convert_common_fractions() {
+        assert_eq!(Some(0.5), deserialize::parse_fraction("Half"));
+        assert_eq!(Some(0.25), deserialize::parse_fraction("OneQuarter"));
+        assert_eq!(
+            Some(-0.75),
+            deserialize::parse_fraction("MinusThreeQuarters")
+        );
+
+        for i in 0..1_000_u64 {
+            let whole = serialize::display_number(i);
+
+            for (fraction, value) in [("AHalf", 0.5), ("AQuarter", 0.25), ("ThreeQuarters", 0.75)] {
+                let number = i as f64 + value;
+                assert_eq!(
+                    Some(number),
+                    deserialize::parse_fraction(&format!("{whole}And{fraction}"))
+                );
+                assert_eq!(
+                    Some(number),
+                    deserialize::parse_fraction(&serialize::display_fraction(number))
+                );
+            }
+        }
+    }
+
+    #[test]
+    pub fn convert_ordinals() {
+        for (word, number) in [
+            ("Zeroth", 0),
+            ("First", 1),
+            ("Second", 2),
+            ("Third", 3),
+            ("Fourth", 4),
+            ("Ninth", 9),
+            ("Twelfth", 12),
+            ("Twentieth", 20),
+            ("TwentyFirst", 21),
+            ("NinetyEighth", 98),
+            ("OneHundredth", 100),
+            ("OneHundredAndFifth", 105),
+            ("OneMillionth", 1_000_000),
+        ] {
+            assert_eq!(Some(BigInt::from(number)), deserialize::parse_ordinal(word));
+            assert_eq!(serialize::display_ordinal(number), word.replace("And", ""));
+        }
+
+        let mut i = 0;
+        while i <= 100_000 {
+            assert_eq!(
+                BigInt::from(i),
+                deserialize::parse_ordinal(&serialize::display_ordinal(i))
+                    .unwrap_or_else(|| panic!("{i} didnt convert"))
+            );
+            i += 1;
+        }
+    }
+
     #[test]
     pub fn all_dont_parse() {
         assert!(deserialize::parse_number("OneOne").is_none());
@@ -58,9 +216,47 @@ mod test {
         assert!(deserialize::parse_number("OneTwenty").is_none());
         assert!(deserialize::parse_number("TenHundred").is_none());
         assert!(deserialize::parse_number("TenThousandFiveMillion").is_none());
+        assert!(deserialize::parse_number("OneThousandDecillion").is_none());
+        assert!(deserialize::parse_number("OneThousandTwoThousand").is_none());
+        assert!(deserialize::parse_number("TwentyHundred").is_none());
+        assert!(deserialize::parse_number("FifteenHundredThousand").is_none());
+        assert!(deserialize::parse_number("OneThousandFifteenHundred").is_none());
+        assert!(deserialize::parse_number("OneHundredAnd").is_none());
+        assert!(deserialize::parse_number("OneAndFive").is_none());
+        assert!(deserialize::parse_number("OneThousandAndOneHundred").is_none());
+
+        assert!(deserialize::parse_ordinal("One").is_none());
+        assert!(deserialize::parse_ordinal("Oneth").is_none());
+        assert!(deserialize::parse_ordinal("Twentyth").is_none());
+        assert!(deserialize::parse_ordinal("FirstOne").is_none());
+        assert!(deserialize::parse_ordinal("MinusFirst").is_none());
+        assert!(deserialize::parse_ordinal("Hundredth").is_none());
+
+        assert!(deserialize::parse_integer("Minus").is_none());
+        assert!(deserialize::parse_integer("MinusMinusOne").is_none());
 
         assert!(deserialize::parse_fraction("OnePointOnePointOne").is_none());
         assert!(deserialize::parse_fraction("OnePointOneTen").is_none());
         assert!(deserialize::parse_fraction("OnePointOneMillion").is_none());
+        assert!(deserialize::parse_fraction("AHalf").is_none());
+        assert!(deserialize::parse_fraction("TwoAndHalf").is_none());
+        assert!(deserialize::parse_fraction("TwoHalf").is_none());
+        assert!(deserialize::parse_fraction("HalfAndAHalf").is_none());
+    }
+
+    #[test]
+    pub fn huge_powers() {
+        assert_eq!(
+            Number::Integer(2).pow(&Number::Integer(100)),
+            Number::from(BigInt::from(2).pow(100))
+        );
+        assert_eq!(
+            Number::Integer(3).pow(&Number::Integer(4_000_000_000)),
+            Number::Float(f64::INFINITY)
+        );
+        assert_eq!(
+            Number::Integer(1).pow(&Number::Integer(4_000_000_000)),
+            Number::Integer(1)
+        );
     }
 }
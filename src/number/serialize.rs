@@ -1,20 +1,22 @@
-const LARGEST: u64 = 1_000_000_000_000_000_000;
+use num_bigint::BigInt;
 
-/// Generates the name for a factor
+/// The names of the factors, where the name at index n belongs to 1000 ** n
 ///
 /// A factor is anything like Thousand, Million, etc.
-fn factor_name(number: u64) -> &'static str {
-    match number {
-        1 => "",
-        1_000 => "Thousand",
-        1_000_000 => "Million",
-        1_000_000_000 => "Billion",
-        1_000_000_000_000 => "Trillion",
-        1_000_000_000_000_000 => "Quadrillion",
-        1_000_000_000_000_000_000 => "Quintillion",
-        _ => panic!("Factor does not conform to 1000 ** n"),
-    }
-}
+pub const FACTOR_NAMES: [&str; 12] = [
+    "",
+    "Thousand",
+    "Million",
+    "Billion",
+    "Trillion",
+    "Quadrillion",
+    "Quintillion",
+    "Sextillion",
+    "Septillion",
+    "Octillion",
+    "Nonillion",
+    "Decillion",
+];
 
 /// Adds a triplet of digits to the string
 ///
@@ -73,7 +75,7 @@ fn append_triplet_to_name(word: &mut String, mut triplet: u64) {
     // and the third syllable is not zero, then we add it through this function.
     // We dont add zeros, because 20 != TwentyZero and its only done for numbers above
     // 20, because 18 != EighteenEight
-    if matches!(triplet, 21..100) && triplet % 10 != 0 {
+    if matches!(triplet, 21..100) && !triplet.is_multiple_of(10) {
         append_triplet_to_name(word, triplet % 10);
     }
 }
@@ -86,43 +88,128 @@ fn append_triplet_to_name(word: &mut String, mut triplet: u64) {
 /// use marble::number::serialize;
 /// assert_eq!(serialize::display_number(42), "FortyTwo");
 /// ```
-pub fn display_number(mut number: u64) -> String {
-    let mut factor = LARGEST;
+pub fn display_number(number: u64) -> String {
+    display_digits(&number.to_string()).expect("Every u64 is smaller than the largest factor")
+}
+
+/// Converts the decimal digits of a whole number to its written form
+///
+/// Returns None, if the number has more digits than the largest factor allows
+fn display_digits(digits: &str) -> Option<String> {
+    let triplets = digits.len().div_ceil(3);
+
+    if triplets > FACTOR_NAMES.len() {
+        return None;
+    }
+
     let mut word = String::new();
 
     // We process the number in triplets - batches of three digits
     // This is easy, as phrases like OneHundredFortyTwo can have any factor following them
-    while factor != 0 {
-        // Extract the triplet from the number
-        let triplet = number / factor;
+    // The first triplet may be shorter, so the triplets are counted from the end
+    for factor in (0..triplets).rev() {
+        let start = digits.len().saturating_sub(factor * 3 + 3);
+        let triplet: u64 = digits[start..digits.len() - factor * 3].parse().ok()?;
 
         // We don't check 000, as that would lead to ZeroMillionZeroThousand ...
         // Except for when the factor is one and nothing has been output yet
         // This allows 0 -> Zero
-        if triplet > 0 || (factor == 1 && word.is_empty()) {
+        if triplet > 0 || (factor == 0 && word.is_empty()) {
             append_triplet_to_name(&mut word, triplet);
-            word.push_str(factor_name(factor));
+            word.push_str(FACTOR_NAMES[factor]);
         }
+    }
 
-        // Remove the triplet and go to the next factor
-        number %= factor;
-        factor /= 1000;
+    Some(word)
+}
+
+/// Converts a whole number to its written form as an ordinal
+///
+/// Only the last part is changed from the cardinal number, like in ```TwentyFirst```
+///
+/// ```rust
+/// use marble::number::serialize;
+/// assert_eq!(serialize::display_ordinal(3), "Third");
+/// assert_eq!(serialize::display_ordinal(40), "Fortieth");
+/// ```
+pub fn display_ordinal(number: u64) -> String {
+    let mut word = display_number(number);
+    let split = word
+        .rfind(|c: char| c.is_ascii_uppercase())
+        .expect("Every number has a capitalized part");
+    let last = word.split_off(split);
+
+    match last.as_str() {
+        "One" => word.push_str("First"),
+        "Two" => word.push_str("Second"),
+        "Three" => word.push_str("Third"),
+        "Five" => word.push_str("Fifth"),
+        "Eight" => word.push_str("Eighth"),
+        "Nine" => word.push_str("Ninth"),
+        "Twelve" => word.push_str("Twelfth"),
+        _ => match last.strip_suffix('y') {
+            // Decades like Twenty become Twentieth
+            Some(decade) => word.push_str(&format!("{decade}ieth")),
+            None => word.push_str(&format!("{last}th")),
+        },
     }
 
     word
 }
 
+/// Converts a whole number of any size to its written form
+///
+/// Negative numbers are prefixed with ```"Minus"```
+///
+/// ```rust
+/// use marble::number::serialize;
+/// use num_bigint::BigInt;
+///
+/// let number = BigInt::from(10).pow(21) * -2;
+/// assert_eq!(serialize::display_big_integer(&number), Some("MinusTwoSextillion".to_string()));
+/// ```
+///
+/// Returns None, if the number is larger than the largest factor allows
+pub fn display_big_integer(number: &BigInt) -> Option<String> {
+    let word = display_digits(&number.magnitude().to_string())?;
+
+    if number.sign() == num_bigint::Sign::Minus {
+        Some(format!("Minus{word}"))
+    } else {
+        Some(word)
+    }
+}
+
+/// Converts a signed whole number to its written form
+///
+/// Negative numbers are prefixed with ```"Minus"```
+///
+/// ```rust
+/// use marble::number::serialize;
+/// assert_eq!(serialize::display_integer(-42), "MinusFortyTwo");
+/// ```
+pub fn display_integer(number: i64) -> String {
+    if number < 0 {
+        format!("Minus{}", display_number(number.unsigned_abs()))
+    } else {
+        display_number(number.unsigned_abs())
+    }
+}
+
 /// Converts a decimal number to its written form
 ///
 /// The number is formatted in ```PascalCase```, meaning each part starts with an uppercase letter.
-/// The decimal seperator is the word ```"Point"```
+/// The decimal seperator is the word ```"Point"```, negative numbers are prefixed with ```"Minus"```
 ///
 /// ```rust
 /// use marble::number::serialize;
 /// assert_eq!(serialize::display_fraction(3.14), "ThreePointOneFour");
+/// assert_eq!(serialize::display_fraction(-0.5), "MinusZeroPointFive");
 /// ```
 pub fn display_fraction(mut number: f64) -> String {
-    number = number.abs();
+    if number < 0.0 {
+        return format!("Minus{}", display_fraction(-number));
+    }
 
     // The number is built from two parts, the whole and the fraction
     let whole = display_number(number as u64);
@@ -0,0 +1,453 @@
+//! Optimizations over compiled expressions
+//!
+//! The pass runs right after compiling, before the strictness analysis, and rewrites the
+//! expression without changing what the program prints, evaluates to or fails with:
+//! - Arithmetic builtins, that are applied to number literals, are folded,
+//!   so `Add of One of Two` becomes `Three`
+//! - A function, that is called right away, like the one every `let` desugars to, is reduced
+//!   by substituting its argument into the body
+//! - A binding, that is never used, is removed together with its value, which would never be forced
+//! - Fully applied `True` and `False` are replaced by the branch they return,
+//!   `If` and `Not` by the call of their condition
+//!
+//! Substituting an argument must neither evaluate it more often nor earlier than before.
+//! So it is only substituted, if it is used at most once and not inside of another function,
+//! which could be called many times. Values, identifiers and functions can always be moved
+//! without repeating any work.
+//!
+//! The pass is optional, see [`crate::compiler::Compiler::with_optimization`].
+
+use std::collections::HashMap;
+
+use crate::{
+    builtin,
+    expr::{Expr, ExprRef},
+    identifier::IdentRef,
+    number::Number,
+    token::Token,
+    value::{BuiltIn, Callable, ValueRef},
+};
+
+/// Optimizes the expression, which must still use the identifiers of the compiler
+pub fn optimize(expr: &ExprRef) -> ExprRef {
+    let mut optimizer = Optimizer::default();
+    optimizer.count_uses(expr, &mut Vec::new());
+
+    optimizer.expr(expr)
+}
+
+/// What a variable of the original expression refers to in the optimized one
+enum Binding {
+    /// The variable is still bound by the function at this depth of the optimized expression
+    Bound(usize),
+    /// The variable was substituted by the value, which was optimized at this depth
+    Substituted(ExprRef, usize),
+}
+
+/// Optimizes an expression in a single walk
+///
+/// The bindings, that are substituted, are recorded while walking into their body,
+/// instead of substituting them into the already optimized body afterwards. That way
+/// no part of the expression is walked again for every binding around it.
+#[derive(Default)]
+struct Optimizer {
+    /// The uses of the argument of every function in the original expression, keyed by its address
+    uses: HashMap<usize, (usize, bool)>,
+    /// The variables in scope, the innermost one is last
+    bindings: Vec<Binding>,
+    /// The number of functions around the optimized expression
+    depth: usize,
+}
+
+impl Optimizer {
+    fn expr(&mut self, expr: &ExprRef) -> ExprRef {
+        match expr.expr() {
+            Expr::Call(lhs, rhs) => match lhs.expr() {
+                Expr::Fn(body, captures) => self.binding(lhs, body, captures, rhs, expr.token),
+                _ => call(self.expr(lhs), self.expr(rhs), expr.token),
+            },
+            Expr::Fn(body, captures) => {
+                let body = self.bound(body);
+                Expr::Fn(body, captures.clone()).annotate(expr.token)
+            }
+            Expr::Strict(inner) => Expr::Strict(self.expr(inner)).annotate(expr.token),
+            Expr::Identifier(ident) => self.identifier(*ident, expr.token),
+            Expr::Value(_) | Expr::Global(_) => expr.clone(),
+        }
+    }
+
+    /// Optimizes the call of a function, substituting the argument into its body if it can be moved
+    fn binding(
+        &mut self,
+        function: &ExprRef,
+        body: &ExprRef,
+        captures: &[IdentRef],
+        argument: &ExprRef,
+        token: Token,
+    ) -> ExprRef {
+        let argument = self.expr(argument);
+        let (count, in_function) = self.uses[&(ExprRef::as_ptr(function) as usize)];
+
+        if !movable(&argument, count, in_function) {
+            let body = self.bound(body);
+            let function = Expr::Fn(body, captures.to_vec()).annotate(function.token);
+            return Expr::Call(function, argument).annotate(token);
+        }
+
+        self.bindings
+            .push(Binding::Substituted(argument, self.depth));
+        let body = self.expr(body);
+        self.bindings.pop();
+
+        body
+    }
+
+    /// Optimizes the body of a function, that still binds its argument
+    fn bound(&mut self, body: &ExprRef) -> ExprRef {
+        self.bindings.push(Binding::Bound(self.depth));
+        self.depth += 1;
+        let body = self.expr(body);
+        self.depth -= 1;
+        self.bindings.pop();
+
+        body
+    }
+
+    fn identifier(&self, ident: IdentRef, token: Token) -> ExprRef {
+        match self.bindings.iter().rev().nth(ident) {
+            Some(Binding::Bound(depth)) => Expr::Identifier(self.depth - depth - 1).annotate(token),
+            // The value is moved below all functions, that are between the binding and its use
+            Some(Binding::Substituted(value, depth)) => shift(value, self.depth - depth, 0),
+            // Variables bound outside of the expression are only renumbered
+            None => Expr::Identifier(ident - self.bindings.len() + self.depth).annotate(token),
+        }
+    }
+
+    /// Counts the uses of the argument of every function in the expression
+    ///
+    /// The uses of the functions around the expression are counted in the scopes, the innermost one last
+    fn count_uses(&mut self, expr: &ExprRef, scopes: &mut Vec<(usize, bool)>) {
+        match expr.expr() {
+            Expr::Identifier(ident) => {
+                if let Some((count, in_function)) = scopes.iter_mut().rev().nth(*ident) {
+                    *count += 1;
+                    // The identifier is the number of functions between the binding and the use
+                    *in_function |= *ident > 0;
+                }
+            }
+            Expr::Call(lhs, rhs) => {
+                self.count_uses(lhs, scopes);
+                self.count_uses(rhs, scopes);
+            }
+            Expr::Fn(body, _) => {
+                scopes.push((0, false));
+                self.count_uses(body, scopes);
+                let uses = scopes.pop().expect("The scope of the function was pushed");
+
+                self.uses.insert(ExprRef::as_ptr(expr) as usize, uses);
+            }
+            Expr::Strict(inner) => self.count_uses(inner, scopes),
+            Expr::Value(_) | Expr::Global(_) => {}
+        }
+    }
+}
+
+/// Whether the argument can be substituted for all of its uses without repeating any work
+fn movable(argument: &ExprRef, count: usize, in_function: bool) -> bool {
+    match argument.expr() {
+        Expr::Identifier(_) | Expr::Value(_) | Expr::Global(_) => true,
+        Expr::Fn(_, _) => count <= 1,
+        Expr::Call(_, _) | Expr::Strict(_) => count == 0 || (count == 1 && !in_function),
+    }
+}
+
+/// Builds the call of the optimized function with the optimized argument and simplifies it
+fn call(function: ExprRef, argument: ExprRef, token: Token) -> ExprRef {
+    if let Expr::Fn(body, _) = function.expr()
+        && let Some(reduced) = reduce(body, &argument)
+    {
+        return reduced;
+    }
+
+    let expr = Expr::Call(function, argument).annotate(token);
+    simplify(&expr).unwrap_or(expr)
+}
+
+/// Substitutes the argument into the already optimized body of the called function,
+/// if that doesn't repeat any work
+fn reduce(body: &ExprRef, argument: &ExprRef) -> Option<ExprRef> {
+    let (count, in_function) = uses(body, 0);
+
+    movable(argument, count, in_function).then(|| substitute(body, 0, argument).0)
+}
+
+/// Simplifies a fully applied arithmetic builtin or Church boolean combinator
+fn simplify(expr: &ExprRef) -> Option<ExprRef> {
+    // The calls are collected from the outermost to the innermost, which calls the head
+    let mut calls = Vec::new();
+    let mut head = expr;
+
+    while let Expr::Call(lhs, _) = head.expr() {
+        calls.push(head);
+        head = lhs;
+    }
+
+    calls.reverse();
+    let arguments: Vec<_> = calls
+        .iter()
+        .map(|call| match call.expr() {
+            Expr::Call(_, argument) => argument,
+            _ => unreachable!("Only calls are collected"),
+        })
+        .collect();
+
+    let value = known(head)?;
+
+    match (value.callable(), arguments.as_slice()) {
+        (Some(Callable::Builtin(built_in)), [lhs, rhs]) => {
+            let number = fold_binary(built_in, number(lhs)?, number(rhs)?)?;
+            Some(Expr::Value(ValueRef::Number(number)).annotate(expr.token))
+        }
+        (Some(Callable::Builtin(built_in)), [operand]) => {
+            let number = fold_unary(built_in, number(operand)?)?;
+            Some(Expr::Value(ValueRef::Number(number)).annotate(expr.token))
+        }
+        (_, [then, _]) if ValueRef::ptr_eq(&value, &builtin::TRUE) => Some((*then).clone()),
+        (_, [_, otherwise]) if ValueRef::ptr_eq(&value, &builtin::FALSE) => {
+            Some((*otherwise).clone())
+        }
+        (_, [condition, then, otherwise]) if ValueRef::ptr_eq(&value, &builtin::IF) => Some(call(
+            call((*condition).clone(), (*then).clone(), calls[1].token),
+            (*otherwise).clone(),
+            calls[2].token,
+        )),
+        (_, [condition, then, otherwise]) if ValueRef::ptr_eq(&value, &builtin::NOT) => Some(call(
+            call((*condition).clone(), (*otherwise).clone(), calls[1].token),
+            (*then).clone(),
+            calls[2].token,
+        )),
+        _ => None,
+    }
+}
+
+/// Returns the value of the expression, if it is known
+fn known(expr: &ExprRef) -> Option<ValueRef> {
+    match expr.expr() {
+        Expr::Value(value) | Expr::Global(value) => Some(value.clone()),
+        _ => None,
+    }
+}
+
+/// Returns the number of the expression, if it is a number literal
+fn number(expr: &ExprRef) -> Option<Number> {
+    match known(expr)? {
+        ValueRef::Number(number) => Some(number),
+        _ => None,
+    }
+}
+
+/// Applies the arithmetic builtin to both operands, like the interpreter would
+///
+/// `Pow` isn't folded, as a lazy program might never need a power, that is too large to compute
+fn fold_binary(built_in: &BuiltIn, lhs: Number, rhs: Number) -> Option<Number> {
+    match built_in {
+        BuiltIn::Add => Some(&lhs + &rhs),
+        BuiltIn::Sub => Some(&lhs - &rhs),
+        BuiltIn::Mul => Some(&lhs * &rhs),
+        BuiltIn::Div => Some(&lhs / &rhs),
+        BuiltIn::Mod => Some(lhs.rem_euclid(&rhs)),
+        BuiltIn::Min => Some(lhs.min(&rhs)),
+        BuiltIn::Max => Some(lhs.max(&rhs)),
+        _ => None,
+    }
+}
+
+/// Applies the arithmetic builtin to the operand, like the interpreter would
+fn fold_unary(built_in: &BuiltIn, operand: Number) -> Option<Number> {
+    match built_in {
+        BuiltIn::Floor => Some(operand.floor()),
+        BuiltIn::Ceil => Some(operand.ceil()),
+        BuiltIn::Round => Some(operand.round()),
+        BuiltIn::Abs => Some(operand.abs()),
+        BuiltIn::Sqrt => Some(operand.sqrt()),
+        _ => None,
+    }
+}
+
+/// Counts the uses of the identifier and returns, whether any of them is inside of a function
+fn uses(expr: &ExprRef, ident: IdentRef) -> (usize, bool) {
+    match expr.expr() {
+        Expr::Identifier(other) => (usize::from(*other == ident), false),
+        Expr::Call(lhs, rhs) => {
+            let (lhs_count, lhs_in_function) = uses(lhs, ident);
+            let (rhs_count, rhs_in_function) = uses(rhs, ident);
+            (lhs_count + rhs_count, lhs_in_function || rhs_in_function)
+        }
+        Expr::Fn(body, _) => {
+            let (count, _) = uses(body, ident + 1);
+            (count, count > 0)
+        }
+        Expr::Strict(inner) => uses(inner, ident),
+        Expr::Value(_) | Expr::Global(_) => (0, false),
+    }
+}
+
+/// Replaces the identifier with the value and removes its binding
+///
+/// The body is already optimized, so only the calls around the substituted value may be
+/// optimized further. These are simplified again, on the way back up from the value.
+/// Returns the expression and whether the value was substituted into it.
+fn substitute(expr: &ExprRef, ident: IdentRef, value: &ExprRef) -> (ExprRef, bool) {
+    match expr.expr() {
+        // The value is moved below all functions, that are between the binding and its use
+        Expr::Identifier(other) if *other == ident => (shift(value, ident, 0), true),
+        // Identifiers outside of the binding are one binding closer now
+        Expr::Identifier(other) if *other > ident => {
+            (Expr::Identifier(other - 1).annotate(expr.token), false)
+        }
+        Expr::Identifier(_) | Expr::Value(_) | Expr::Global(_) => (expr.clone(), false),
+        Expr::Call(lhs, rhs) => {
+            let (lhs, lhs_substituted) = substitute(lhs, ident, value);
+            let (rhs, rhs_substituted) = substitute(rhs, ident, value);
+
+            if lhs_substituted || rhs_substituted {
+                (call(lhs, rhs, expr.token), true)
+            } else {
+                (Expr::Call(lhs, rhs).annotate(expr.token), false)
+            }
+        }
+        Expr::Fn(body, captures) => {
+            let (body, substituted) = substitute(body, ident + 1, value);
+            (
+                Expr::Fn(body, captures.clone()).annotate(expr.token),
+                substituted,
+            )
+        }
+        Expr::Strict(inner) => {
+            let (inner, substituted) = substitute(inner, ident, value);
+            (Expr::Strict(inner).annotate(expr.token), substituted)
+        }
+    }
+}
+
+/// Shifts the identifiers, that are bound outside of the expression, by the amount
+fn shift(expr: &ExprRef, amount: usize, depth: usize) -> ExprRef {
+    if amount == 0 {
+        return expr.clone();
+    }
+
+    match expr.expr() {
+        Expr::Identifier(ident) if *ident >= depth => {
+            Expr::Identifier(ident + amount).annotate(expr.token)
+        }
+        Expr::Identifier(_) | Expr::Value(_) | Expr::Global(_) => expr.clone(),
+        Expr::Call(lhs, rhs) => {
+            Expr::Call(shift(lhs, amount, depth), shift(rhs, amount, depth)).annotate(expr.token)
+        }
+        Expr::Fn(body, captures) => {
+            Expr::Fn(shift(body, amount, depth + 1), captures.clone()).annotate(expr.token)
+        }
+        Expr::Strict(inner) => Expr::Strict(shift(inner, amount, depth)).annotate(expr.token),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        EvalOptions, compile_with,
+        expr::{Expr, ExprRef},
+        number::Number,
+        source::Source,
+        value::ValueRef,
+    };
+
+    fn optimized(code: &str) -> ExprRef {
+        let options = EvalOptions {
+            optimize: true,
+            ..Default::default()
+        };
+        compile_with(&Source::new(code), &options).unwrap()
+    }
+
+    fn number(code: &str) -> Option<Number> {
+        match optimized(code).expr() {
+            Expr::Value(ValueRef::Number(number)) => Some(number.clone()),
+            _ => None,
+        }
+    }
+
+    fn count_calls(expr: &ExprRef) -> usize {
+        match expr.expr() {
+            Expr::Call(lhs, rhs) => 1 + count_calls(lhs) + count_calls(rhs),
+            Expr::Fn(body, _) => count_calls(body),
+            Expr::Strict(inner) => count_calls(inner),
+            Expr::Identifier(_) | Expr::Value(_) | Expr::Global(_) => 0,
+        }
+    }
+
+    #[test]
+    pub fn constant_folding() {
+        assert_eq!(number("Add of One of Two"), Some(Number::Integer(3)));
+        assert_eq!(
+            number("Mul of do Sub of Ten of Four end of OnePointFive"),
+            Some(Number::Float(9.0))
+        );
+        assert_eq!(number("Sqrt of Sixteen"), Some(Number::Integer(4)));
+        assert_eq!(number("Pow of Two of Ten"), None);
+        assert_eq!(number("Add of One of str Two ing"), None);
+    }
+
+    #[test]
+    pub fn bindings() {
+        assert_eq!(
+            number("let X be Add of One of Two in Mul of X of X"),
+            Some(Number::Integer(9))
+        );
+        assert_eq!(
+            number("let Double be fn X do Add of X of X end in Double of Two"),
+            Some(Number::Integer(4))
+        );
+        assert_eq!(
+            number("let Unused be PrintLn of One in Two"),
+            Some(Number::Integer(2))
+        );
+
+        // Substituting would print on every call of the function, instead of once
+        assert!(matches!(
+            optimized("let X be PrintLn of One in fn Y do X end").expr(),
+            Expr::Call(_, _)
+        ));
+    }
+
+    #[test]
+    pub fn cascading_bindings() {
+        // Every binding is substituted into the ones after it, like in a generated file
+        let bindings: String = (0..500)
+            .map(|idx| format!("let X{} be Max of X{idx} of X{idx} in\n", idx + 1))
+            .collect();
+        let code = format!("comment digits\nlet X0 be 1 in\n{bindings}X500");
+
+        assert_eq!(number(&code), Some(Number::Integer(1)));
+    }
+
+    #[test]
+    pub fn church_booleans() {
+        assert_eq!(number("If of True of One of Two"), Some(Number::Integer(1)));
+        assert_eq!(
+            number("If of False of One of Two"),
+            Some(Number::Integer(2))
+        );
+        assert_eq!(
+            number("Not of True of One of Two"),
+            Some(Number::Integer(2))
+        );
+        assert_eq!(
+            number("let Cond be Not of False in If of Cond of One of Two"),
+            Some(Number::Integer(1))
+        );
+        assert_eq!(
+            count_calls(&optimized("If of do Is of One of Two end of One of Two")),
+            4
+        );
+    }
+}
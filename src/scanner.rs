@@ -13,9 +13,14 @@
 //! - 'comment' -> Starts a single line comment, ending at the next newline
 //! - 'com' -> Starts a multi line comment, endig at the word 'ment'
 //! - 'fn', 'of', 'do', 'end', 'let', 'be' and 'in' -> Keywords
-//! - Any numeric words, like 'One', 'FortyTwo' or 'ThreePointOne' -> Number literals
+//! - Any numeric words, like 'One', 'MinusFortyTwo' or 'ThreePointOne' -> Number literals
 //! - Every other word -> An identifier
 //!
+//! Decimal literals like '42' or '-3.14' are only number literals in digit mode. Otherwise they
+//! are identifiers, that may be bound like any other. Digit mode is enabled with
+//! [`Scanner::with_digits`] or by starting the file with the line 'comment digits'. In that mode
+//! digit words can't be used as identifiers anymore, so 'let 3 be Three in' fails to compile.
+//!
 //! UTF-8 is fully supported in strings, comments and identifiers.
 
 use std::{iter::Peekable, str::Chars};
@@ -23,16 +28,20 @@ use std::{iter::Peekable, str::Chars};
 use line_index::TextRange;
 
 use crate::{
-    number::deserialize,
+    number::Number,
     source::Source,
     token::{Token, TokenType},
 };
 
+/// The first line of a file, that enables digit mode
+const DIGITS_PRAGMA: &str = "comment digits";
+
 pub struct Scanner<'a> {
     start: usize,
     current: usize,
     chars: Peekable<Chars<'a>>,
     source: &'a Source<'a>,
+    digits: bool,
 }
 
 impl Iterator for Scanner<'_> {
@@ -50,12 +59,20 @@ impl<'a> Scanner<'a> {
             current: 0,
             source,
             chars: source.str.chars().peekable(),
+            digits: source.str.lines().next().map(str::trim_end) == Some(DIGITS_PRAGMA),
         }
     }
 
+    /// Scans decimal literals like ```42``` or ```3.14``` as numbers instead of identifiers
+    pub fn with_digits(&mut self, digits: bool) -> &Self {
+        self.digits = digits;
+
+        self
+    }
+
     /// Consumes the next word.
     /// A word consists of any characters, that are not ascii whitespace.
-    fn next_word(&mut self) -> &str {
+    fn next_word(&mut self) -> &'a str {
         self.consume_non_whitespace();
         &self.source.str[self.start..self.current]
     }
@@ -72,9 +89,15 @@ impl<'a> Scanner<'a> {
             "string" => self.create_token(TokenType::String(true)),
             "str" => self.string(),
             "com" => {
-                // The comment is consumed but not returned
-                self.multiline_comment();
-                self.next_token()
+                // Only the opening 'com' is returned, so that errors point at it
+                let opening = self.create_token(TokenType::Comment(false));
+
+                // The comment is consumed but only returned, if it is unterminated
+                if self.multiline_comment() {
+                    self.next_token()
+                } else {
+                    opening
+                }
             }
             "comment" => {
                 self.comment();
@@ -83,8 +106,8 @@ impl<'a> Scanner<'a> {
             word => {
                 if let Some(keyword_type) = Self::check_keyword(word) {
                     self.create_token(keyword_type)
-                } else if let Some(number) = Self::check_number(word) {
-                    self.create_token(TokenType::Number(number))
+                } else if self.is_number(word) {
+                    self.create_token(TokenType::Number)
                 } else {
                     self.create_token(TokenType::Identifier)
                 }
@@ -111,8 +134,15 @@ impl<'a> Scanner<'a> {
         }
     }
 
-    fn check_number(word: &str) -> Option<f64> {
-        deserialize::parse_fraction(word)
+    fn is_number(&self, word: &str) -> bool {
+        Number::from_words(word).is_some() || (self.digits && Self::is_decimal(word))
+    }
+
+    /// Checks for decimal literals, excluding other forms floats can be parsed from, like ```1e5```
+    fn is_decimal(word: &str) -> bool {
+        word.chars()
+            .all(|c| c.is_ascii_digit() || c == '-' || c == '.')
+            && Number::from_digits(word).is_some()
     }
 
     fn comment(&mut self) -> Token {
@@ -120,18 +150,19 @@ impl<'a> Scanner<'a> {
             self.consume();
         }
 
-        self.create_token(TokenType::Comment)
+        self.create_token(TokenType::Comment(true))
     }
 
-    fn multiline_comment(&mut self) -> Token {
-        self.consume_until("ment");
-        self.create_token(TokenType::Comment)
+    /// Consumes the comment and returns whether it is terminated
+    fn multiline_comment(&mut self) -> bool {
+        self.consume_until("ment")
     }
 
     fn create_token(&self, token_type: TokenType) -> Token {
         Token {
             range: TextRange::new((self.start as u32).into(), (self.current as u32).into()),
             token_type,
+            file: self.source.file,
         }
     }
 
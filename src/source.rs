@@ -1,11 +1,27 @@
+use std::sync::Arc;
+
 use line_index::{LineCol, LineIndex};
 
 use crate::token::Token;
 
+/// Identifies the file a token was scanned from during a run.
+/// The main source of a run is always file `0`, imported files are numbered from `1`.
+pub type FileId = usize;
+
+pub type FileRef = Arc<SourceFile>;
+
+/// An imported file, kept so that errors can point into it after it was evaluated
+#[derive(Debug)]
+pub struct SourceFile {
+    pub name: String,
+    pub code: String,
+}
+
 #[derive(Clone)]
 pub struct Source<'a> {
     pub str: &'a str,
     pub idx: LineIndex,
+    pub file: FileId,
 }
 
 impl<'a> Source<'a> {
@@ -34,9 +50,14 @@ impl<'a> Source<'a> {
 
     #[inline]
     pub fn new(source: &'a str) -> Self {
+        Self::in_file(source, 0)
+    }
+
+    pub fn in_file(source: &'a str, file: FileId) -> Self {
         Self {
             str: source,
             idx: LineIndex::new(source),
+            file,
         }
     }
 }
@@ -0,0 +1,226 @@
+//! Strictness analysis over compiled expressions
+//!
+//! Every argument of a call is wrapped in a lazy call, even if the called function forces it
+//! right away. This pass marks arguments, that are always forced once their call is forced,
+//! as [`Expr::Strict`], so that they are evaluated in place instead.
+//!
+//! An argument is strict, if the head of the call is known to force it:
+//! - Builtins force their arguments, e.g. both operands of `Add`
+//! - `If`, `Not`, `And` and `Or` force the Church boolean they are given, once they are fully applied
+//! - `True` and `False` force the argument they return, once they are fully applied
+//!
+//! The head of a call is known, if it is a value, a global binding like the default bindings,
+//! or an identifier, that is bound to a known value by a `let`.
+//!
+//! Callees, like the left side of `then`, are always forced, so they are evaluated in place
+//! by the interpreter without being marked.
+
+use crate::{
+    builtin,
+    expr::{Expr, ExprRef},
+    value::{Callable, ValueRef},
+};
+
+/// Marks all arguments in the expression, that are always forced
+pub fn analyse(expr: &ExprRef) -> ExprRef {
+    Analysis::default().expr(expr)
+}
+
+#[derive(Default)]
+struct Analysis {
+    /// The known values of the bindings in scope, the innermost binding is last
+    bindings: Vec<Option<ValueRef>>,
+}
+
+impl Analysis {
+    fn expr(&mut self, expr: &ExprRef) -> ExprRef {
+        match expr.expr() {
+            Expr::Call(_, _) => self.call(expr),
+            Expr::Fn(body, captures) => {
+                let body = self.bind(None, body);
+                Expr::Fn(body, captures.clone()).annotate(expr.token)
+            }
+            Expr::Strict(inner) => Expr::Strict(self.expr(inner)).annotate(expr.token),
+            Expr::Identifier(_) | Expr::Value(_) | Expr::Global(_) => expr.clone(),
+        }
+    }
+
+    fn call(&mut self, expr: &ExprRef) -> ExprRef {
+        // The calls are collected from the outermost to the innermost, which calls the head
+        let mut calls = Vec::new();
+        let mut head = expr;
+
+        while let Expr::Call(lhs, _) = head.expr() {
+            calls.push(head);
+            head = lhs;
+        }
+
+        let strict = self.strict_arguments(head, calls.len());
+
+        let mut result = match (head.expr(), calls.as_slice()) {
+            // A let binds the value to the functions argument
+            (Expr::Fn(body, captures), [call]) => {
+                let Expr::Call(_, value) = call.expr() else {
+                    unreachable!("Only calls are collected")
+                };
+
+                let known = self.known(value);
+                Expr::Fn(self.bind(known, body), captures.clone()).annotate(head.token)
+            }
+            _ => self.expr(head),
+        };
+
+        for (call, is_strict) in calls.iter().rev().zip(strict) {
+            let Expr::Call(_, argument) = call.expr() else {
+                unreachable!("Only calls are collected")
+            };
+
+            let mut argument = self.expr(argument);
+
+            // Only calls are worth marking, everything else is evaluated without a lazy call anyway
+            if is_strict && matches!(argument.expr(), Expr::Call(_, _)) {
+                argument = Expr::Strict(argument.clone()).annotate(argument.token);
+            }
+
+            result = Expr::Call(result, argument).annotate(call.token);
+        }
+
+        result
+    }
+
+    fn bind(&mut self, value: Option<ValueRef>, body: &ExprRef) -> ExprRef {
+        self.bindings.push(value);
+        let body = self.expr(body);
+        self.bindings.pop();
+        body
+    }
+
+    /// Returns the value of the expression, if it is known
+    fn known(&self, expr: &ExprRef) -> Option<ValueRef> {
+        match expr.expr() {
+            Expr::Value(value) | Expr::Global(value) => Some(value.clone()),
+            Expr::Identifier(ident) => self
+                .bindings
+                .len()
+                .checked_sub(ident + 1)
+                .and_then(|idx| self.bindings[idx].clone()),
+            Expr::Strict(inner) => self.known(inner),
+            _ => None,
+        }
+    }
+
+    /// Returns for each of the arguments, whether it is forced by the head
+    fn strict_arguments(&self, head: &ExprRef, count: usize) -> Vec<bool> {
+        let mut strict = vec![false; count];
+
+        let Some(value) = self.known(head) else {
+            return strict;
+        };
+
+        if let Some(Callable::Builtin(built_in)) = value.callable() {
+            let forced = built_in.forced_arguments().min(count);
+            strict[..forced].fill(true);
+        } else if let Some((arity, position)) = forced_boolean(&value)
+            && count >= arity
+        {
+            strict[position] = true;
+        }
+
+        strict
+    }
+}
+
+/// Returns how many arguments a Church boolean combinator needs,
+/// before it forces the argument at the returned position
+fn forced_boolean(value: &ValueRef) -> Option<(usize, usize)> {
+    [
+        (&*builtin::IF, 3, 0),
+        (&*builtin::NOT, 3, 0),
+        (&*builtin::AND, 2, 0),
+        (&*builtin::OR, 2, 0),
+        (&*builtin::TRUE, 2, 0),
+        (&*builtin::FALSE, 2, 1),
+    ]
+    .into_iter()
+    .find(|(combinator, _, _)| ValueRef::ptr_eq(combinator, value))
+    .map(|(_, arity, position)| (arity, position))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        compile,
+        expr::{Expr, ExprRef},
+        source::Source,
+    };
+
+    fn count_strict(expr: &ExprRef) -> usize {
+        match expr.expr() {
+            Expr::Call(lhs, rhs) => count_strict(lhs) + count_strict(rhs),
+            Expr::Fn(body, _) => count_strict(body),
+            Expr::Strict(inner) => 1 + count_strict(inner),
+            Expr::Identifier(_) | Expr::Value(_) | Expr::Global(_) => 0,
+        }
+    }
+
+    fn strict_arguments(code: &str) -> usize {
+        count_strict(&compile(&Source::new(code)).unwrap())
+    }
+
+    #[test]
+    pub fn builtin_operands() {
+        assert_eq!(strict_arguments("Add of One of Two"), 0);
+        assert_eq!(
+            strict_arguments("Add of do Add of One of Two end of One"),
+            1
+        );
+        assert_eq!(
+            strict_arguments("Add of do Add of One of Two end of do Sub of One of Two end"),
+            2
+        );
+        assert_eq!(
+            strict_arguments("let + be Add in + of do + of One of Two end of One"),
+            1
+        );
+    }
+
+    #[test]
+    pub fn church_booleans() {
+        assert_eq!(
+            strict_arguments("If of do Is of One of Two end of One of Two"),
+            1
+        );
+        assert_eq!(strict_arguments("If of do Is of One of Two end of One"), 0);
+        assert_eq!(
+            strict_arguments("Not of do Is of One of Two end of One of Two"),
+            1
+        );
+        assert_eq!(strict_arguments("Not of do Is of One of Two end"), 0);
+        assert_eq!(
+            strict_arguments("True of do Add of One of Two end of One"),
+            1
+        );
+        assert_eq!(
+            strict_arguments("False of do Add of One of Two end of One"),
+            0
+        );
+        assert_eq!(
+            strict_arguments("False of One of do Add of One of Two end"),
+            1
+        );
+    }
+
+    #[test]
+    pub fn unknown_functions() {
+        assert_eq!(
+            strict_arguments("fn X do X end of do Add of One of Two end"),
+            0
+        );
+        assert_eq!(
+            strict_arguments(
+                "let Add be fn X Y do X end in Add of do Add of One of Two end of One"
+            ),
+            0
+        );
+    }
+}
@@ -1,4 +1,26 @@
-use crate::{error::Error, evaluate_file_at, execute_file_at, value::Value};
+use std::{
+    fs::{read_dir, read_to_string},
+    io::{Cursor, sink, stdin},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use crate::{
+    EvalOptions, OutputResult, builtin, compile,
+    compiler::Compiler,
+    environment::Environment,
+    error::Error,
+    evaluate_code_with, evaluate_file_at, execute_file_at, execute_string_with_limits,
+    expr::Expr,
+    interpreter::{Backend, Interpreter, ValueResult},
+    limits::Limits,
+    number::Number,
+    scanner::Scanner,
+    source::Source,
+    token::TokenType,
+    value::{Partial, Thunk, Value, ValueRef},
+};
 
 pub fn file_name(name: &str) -> String {
     format!("examples/test/{name}.mrbl")
@@ -18,9 +40,7 @@ macro_rules! expect_value {
         make_test!(
             $name,
             assert!(matches!(
-                evaluate_file_at(&file_name(stringify!($name)))
-                    .unwrap()
-                    .as_ref(),
+                evaluate_file_at(&file_name(stringify!($name))).unwrap(),
                 $pattern
             ))
         );
@@ -54,21 +74,652 @@ macro_rules! expect_output {
     };
 }
 
-expect_value!(fact, Value::Number(120.0));
-expect_value!(logic, Value::Number(1.0));
+expect_value!(fact, ValueRef::Number(Number::Integer(120)));
+expect_value!(logic, ValueRef::Number(Number::Integer(1)));
 
 expect_error!(error_undefined, Error::IdentifierIsNotDefined(_));
 
 expect_output!(hello_world, "Hello World\n");
 
-expect_value!(arithmetic, Value::Number(10.0));
+expect_value!(arithmetic, ValueRef::Number(Number::Integer(10)));
+
+expect_value!(comparison, ValueRef::Number(Number::Integer(1052)));
+
+expect_output!(
+    integers,
+    "9000000000000000001\n-2.5\n-2\nMinusOneHundredTwentyThree\n0.5\n90000000000000000000\n"
+);
 
-expect_value!(simple_fib, Value::Number(377.0));
+expect_output!(
+    big_integers,
+    "10333147966386144929666651337523200000000\nOneSextillion\n35\n"
+);
 
-expect_value!(tuple_fib, Value::Number(12586269025.0));
+expect_value!(simple_fib, ValueRef::Number(Number::Integer(377)));
 
-// expect_value!(looping, Value::Number(1.0));
+expect_value!(tuple_fib, ValueRef::Number(Number::Integer(12586269025)));
+
+expect_value!(looping, ValueRef::Number(Number::Integer(1)));
+
+expect_value!(accumulate, ValueRef::Number(Number::Integer(50005000)));
+
+expect_value!(counting, ValueRef::Number(Number::Integer(100000)));
 
 expect_output!(lazy_unwrapping, "Once");
 
-expect_value!(import, Value::Number(3.0));
+expect_value!(import, ValueRef::Number(Number::Integer(3)));
+
+expect_output!(import_cache, "Loaded\n");
+
+expect_output!(strict_arguments, "FunctionAB");
+
+expect_value!(list, ValueRef::Number(Number::Integer(15)));
+
+expect_output!(list_display, "[1, Two]\n");
+
+expect_output!(list_display_lazy, "AB[3, [4]]\n{Sum: 7}\n");
+
+expect_output!(list_equality, "EqualDifferentEqual");
+
+expect_error!(list_empty, Error::ListIsEmpty("Head"));
+
+expect_value!(record, ValueRef::Number(Number::Integer(16)));
+
+expect_output!(record_display, "{Name: Marble, Tags: [1]}\n");
+
+expect_error!(record_missing, Error::KeyNotInRecord(_));
+
+expect_output!(
+    strings,
+    "Hello,Marble\nmarble!\nn\nContains\nDoesn't start\n"
+);
+
+expect_error!(
+    substring_bounds,
+    Error::IndexOutOfBounds(Number::Integer(7), 6)
+);
+
+expect_output!(conversion, "Half is 2.5\nTwoPointFive\n142\n0\n23\n");
+
+expect_value!(digits, ValueRef::Number(Number::Integer(42)));
+
+expect_error!(digits_binding, Error::ExpectedIdentifierAsVariableName);
+
+#[test]
+fn digits_disabled() {
+    let code = read_to_string(file_name("digits_binding")).unwrap();
+    let source = Source::new(&code);
+    let mut scanner = Scanner::new(&source);
+    scanner.with_digits(false);
+    let mut compiler = Compiler::new(&source, scanner);
+    compiler.with_bindings(Compiler::default_bindings());
+
+    assert!(compiler.compile_all().errors.is_empty());
+}
+
+expect_error!(unterminated_string, Error::UnterminatedString);
+
+expect_error!(unterminated_comment, Error::UnterminatedComment);
+
+#[test]
+fn unterminated_allowed() {
+    let code = read_to_string(file_name("unterminated_comment")).unwrap();
+    let source = Source::new(&code);
+    let mut compiler = Compiler::new(&source, Scanner::new(&source));
+    compiler.with_unterminated_allowed(true);
+    let compilation = compiler.compile_all();
+
+    assert!(compilation.errors.is_empty());
+    assert!(matches!(
+        compilation.warnings.as_slice(),
+        [warning] if matches!(warning.error, Error::UnterminatedComment)
+    ));
+}
+
+#[test]
+fn cyclic_import() {
+    let error = evaluate_file_at(&file_name("cyclic_import")).unwrap_err();
+
+    assert!(
+        error
+            .error
+            .to_string()
+            .contains("'import/cycle_a' -> 'cycle_b' -> 'cycle_a'")
+    );
+}
+
+#[test]
+fn stack_trace() {
+    let code = read_to_string(file_name("stack_trace")).unwrap();
+    let error = evaluate_file_at(&file_name("stack_trace")).unwrap_err();
+    let message = error.of_source(&Source::new(&code));
+
+    assert!(matches!(
+        error.error,
+        Error::ArgumentToOperatorMustBeANumber("Add")
+    ));
+    assert!(message.starts_with("Runtime Error at import/increment:3:9 => 'Add'"));
+    assert!(message.contains("Stack trace:\n"));
+    assert!(message.contains("at 4:21 => 'Increment'"));
+}
+
+#[test]
+fn multiple_errors() {
+    let code = read_to_string(file_name("multiple_errors")).unwrap();
+    let source = Source::new(&code);
+    let mut compiler = Compiler::new(&source, Scanner::new(&source));
+    compiler.with_bindings(Compiler::default_bindings());
+    let errors: Vec<_> = compiler
+        .compile_all()
+        .errors
+        .into_iter()
+        .map(|error| error.error)
+        .collect();
+
+    assert!(matches!(
+        errors.as_slice(),
+        [
+            Error::IdentifierIsNotDefined(_),
+            Error::ExpectedIdentifierAsFunctionArgument,
+            Error::ExpectedEndAfterDoBlock(_),
+            Error::IdentifierIsNotDefined(_),
+        ]
+    ));
+}
+
+#[test]
+fn globals_are_resolved() {
+    let expr = compile(&Source::new("Add of One of Two")).unwrap();
+
+    let Expr::Call(lhs, _) = expr.expr() else {
+        panic!("The program is a call")
+    };
+    let Expr::Call(add, _) = lhs.expr() else {
+        panic!("Add is called with two arguments")
+    };
+
+    assert!(matches!(add.expr(), Expr::Global(_)));
+    assert_eq!(add.token.token_type, TokenType::Identifier);
+}
+
+/// Evaluates the code with a global binding of ```Answer```, like a host would add one
+fn evaluate_with_answer(code: &str) -> ValueResult {
+    let source = Source::new(code);
+    let mut compiler = Compiler::new(&source, Scanner::new(&source));
+    compiler.with_bindings(Compiler::default_bindings());
+    compiler.with_bindings(vec![("Answer", ValueRef::Number(Number::Integer(42)))]);
+    let expr = compiler.compile()?;
+
+    Interpreter::new(
+        Arc::new(Mutex::new(Box::new(stdin()))),
+        Arc::new(Mutex::new(Box::new(sink()))),
+        PathBuf::default(),
+    )
+    .interpret(expr)
+}
+
+#[test]
+fn global_bindings() {
+    assert!(matches!(
+        evaluate_with_answer("Add of Answer of One").unwrap(),
+        ValueRef::Number(Number::Integer(43))
+    ));
+    assert!(matches!(
+        evaluate_with_answer("let Answer be One in Answer").unwrap(),
+        ValueRef::Number(Number::Integer(1))
+    ));
+    assert!(matches!(
+        evaluate_with_answer("let Add be Sub in Add of Answer of Two").unwrap(),
+        ValueRef::Number(Number::Integer(40))
+    ));
+}
+
+#[test]
+fn compiled_closures() {
+    // Functions, that capture outer variables, can be interpreted right after compiling
+    assert!(matches!(
+        evaluate_with_answer("let X be One in let F be fn Y do Add of X of Answer end in F of Two")
+            .unwrap(),
+        ValueRef::Number(Number::Integer(43))
+    ));
+}
+
+#[test]
+fn partial_builtins_in_place() {
+    assert!(matches!(
+        evaluate_with_answer("Add of Answer").unwrap(),
+        ValueRef::Partial(Partial::AddOf(Number::Integer(42)))
+    ));
+    assert!(matches!(
+        evaluate_with_answer("Is of Answer").unwrap(),
+        ValueRef::Partial(Partial::IsOf(Number::Integer(42)))
+    ));
+    assert!(matches!(
+        evaluate_with_answer("Is of str Answer ing").unwrap(),
+        ValueRef::Shared(_)
+    ));
+}
+
+#[test]
+fn partial_builtins_display() {
+    assert_eq!(
+        evaluate_with_answer("Add of Answer").unwrap().to_string(),
+        "Builtin AddOf 42"
+    );
+    assert_eq!(
+        evaluate_with_answer("Div of Half").unwrap().to_string(),
+        "Builtin DivOf 0.5"
+    );
+}
+
+/// Builds a list of the numbers up to the size, whose elements aren't forced yet
+fn lazy_list(size: usize) -> String {
+    format!(
+        "comment digits
+let Z be Import of str lang/z ing in
+let Range be Z of fn Range From To do
+    Is of From of To of do
+        Nil
+    end of do
+        Cons of do Add of From of 0 end of do Range of do Add of From of 1 end of To end
+    end
+end in
+let Numbers be fn Unused do Range of 0 of {size} end in"
+    )
+}
+
+#[test]
+fn large_lazy_lists() {
+    // Showing every element of a lazy list must not take time quadratic in its length
+    let limits = Limits {
+        time: Some(Duration::from_secs(20)),
+        ..Default::default()
+    };
+    let shown = format!(
+        "{}\nStringLength of do ToString of do Numbers of Unit end end",
+        lazy_list(40_000)
+    );
+    let compared = format!(
+        "{}\nIs of do Numbers of Unit end of do Numbers of Unit end of 1 of 0",
+        lazy_list(40_000)
+    );
+    let length = format!("{:?}", (0..40_000).collect::<Vec<_>>()).len();
+
+    assert!(matches!(
+        execute_string_with_limits(&shown, PathBuf::from("examples/test"), limits)
+            .unwrap()
+            .0,
+        ValueRef::Number(Number::Integer(n)) if n as usize == length
+    ));
+    assert!(matches!(
+        execute_string_with_limits(&compared, PathBuf::from("examples/test"), limits)
+            .unwrap()
+            .0,
+        ValueRef::Number(Number::Integer(1))
+    ));
+}
+
+fn execute_with_input(name: &str, input: &str, backend: Backend) -> OutputResult {
+    let mut output = Vec::new();
+    let file = file_name(name);
+    let code = read_to_string(&file).unwrap();
+    evaluate_code_with(
+        &code,
+        Arc::new(Mutex::new(Box::new(Cursor::new(input.as_bytes())))),
+        Arc::new(Mutex::new(Box::new(Cursor::new(&mut output)))),
+        PathBuf::from(file).parent().unwrap().into(),
+        EvalOptions {
+            backend,
+            ..Default::default()
+        },
+    )
+    .map(move |val| (val, String::from_utf8(output).unwrap()))
+}
+
+#[test]
+fn read_input() {
+    let (_, output) =
+        execute_with_input("read_input", "Hello\r\néxy\nz", Backend::TreeWalking).unwrap();
+
+    assert_eq!(output, "Reading\nHello\né\nxy\nzNone\n");
+}
+
+fn execute_optimized(name: &str, backend: Backend, optimize: bool) -> Result<String, String> {
+    let mut output = Vec::new();
+    let file = file_name(name);
+    let code = read_to_string(&file).unwrap();
+    evaluate_code_with(
+        &code,
+        Arc::new(Mutex::new(Box::new(Cursor::new("Input")))),
+        Arc::new(Mutex::new(Box::new(Cursor::new(&mut output)))),
+        PathBuf::from(file).parent().unwrap().into(),
+        EvalOptions {
+            backend,
+            optimize,
+            ..Default::default()
+        },
+    )
+    .map(|value| format!("{value}\n{}", String::from_utf8(output).unwrap()))
+    .map_err(|err| err.error.to_string())
+}
+
+#[test]
+fn optimization_preserves_results() {
+    let examples = read_dir("examples/test").unwrap();
+
+    for entry in examples {
+        let path = entry.unwrap().path();
+        let Some(name) = path.file_stem().and_then(|stem| stem.to_str()) else {
+            continue;
+        };
+
+        if path.is_dir() || name == "diverging" {
+            continue;
+        }
+
+        for backend in [Backend::TreeWalking, Backend::Bytecode] {
+            assert_eq!(
+                execute_optimized(name, backend, false),
+                execute_optimized(name, backend, true),
+                "{name} changed when optimized"
+            );
+        }
+    }
+}
+
+/// Forces a lazy call, that calls a function returning the lazy call itself
+///
+/// Marble has no recursive bindings, so the cycle is tied by memoizing the function by hand
+fn interpret_self_dependent(backend: Backend) -> ValueResult {
+    let function = Value::LazyCall(Thunk::new(
+        Expr::Value(builtin::UNIT.clone()).default_ref(),
+        Environment::root(),
+    ))
+    .new_ref();
+    let call = Expr::Call(
+        Expr::Identifier(0).default_ref(),
+        Expr::Value(builtin::UNIT.clone()).default_ref(),
+    );
+    let lazy = Value::LazyCall(Thunk::new(
+        call.default_ref(),
+        Environment::new(vec![function.clone()]),
+    ))
+    .new_ref();
+
+    let Some(thunk) = function.thunk() else {
+        unreachable!("The function is created lazily")
+    };
+    thunk.memoize(
+        Value::Fn(
+            Expr::Identifier(1).default_ref(),
+            Environment::new(vec![lazy.clone()]),
+        )
+        .new_ref(),
+    );
+
+    let mut interpreter = Interpreter::new(
+        Arc::new(Mutex::new(Box::new(stdin()))),
+        Arc::new(Mutex::new(Box::new(sink()))),
+        PathBuf::default(),
+    );
+    interpreter.with_backend(backend);
+    interpreter.interpret(Expr::Value(lazy).default_ref())
+}
+
+#[test]
+fn value_depends_on_itself() {
+    assert!(matches!(
+        interpret_self_dependent(Backend::TreeWalking)
+            .unwrap_err()
+            .error,
+        Error::ValueDependsOnItself
+    ));
+}
+
+fn execute_with_limits(name: &str, limits: Limits) -> OutputResult {
+    let file = file_name(name);
+    let code = read_to_string(&file).unwrap();
+    execute_string_with_limits(&code, PathBuf::from(file).parent().unwrap().into(), limits)
+}
+
+#[test]
+fn step_limit() {
+    let limits = Limits {
+        steps: Some(10_000),
+        ..Default::default()
+    };
+
+    assert!(matches!(
+        execute_with_limits("diverging", limits).unwrap_err().error,
+        Error::StepLimitExceeded(10_000)
+    ));
+    assert!(execute_with_limits("fact", limits).is_ok());
+}
+
+#[test]
+fn thunk_limit() {
+    // The sum is only forced at the end, so every addition stays alive until then
+    let limits = Limits {
+        thunks: Some(1_000),
+        ..Default::default()
+    };
+
+    assert!(matches!(
+        execute_with_limits("accumulate", limits).unwrap_err().error,
+        Error::ThunkLimitExceeded(1_000)
+    ));
+    assert!(execute_with_limits("looping", limits).is_ok());
+}
+
+#[test]
+fn constant_space() {
+    // Interrupting the loop shows, that the finished iterations left neither a lazy call nor a
+    // continuation behind
+    let limits = Limits {
+        steps: Some(100_000),
+        thunks: Some(100),
+        ..Default::default()
+    };
+    let error = execute_with_limits("counting", limits).unwrap_err();
+
+    assert!(matches!(error.error, Error::StepLimitExceeded(100_000)));
+    assert!(error.trace.calls.len() < 10);
+}
+
+#[test]
+fn time_limit() {
+    let limits = Limits {
+        time: Some(Duration::from_millis(50)),
+        ..Default::default()
+    };
+
+    assert!(matches!(
+        execute_with_limits("diverging", limits).unwrap_err().error,
+        Error::TimeLimitExceeded(_)
+    ));
+}
+
+mod bytecode {
+    use std::{
+        fs::read_to_string,
+        io::{Cursor, stdin},
+        path::PathBuf,
+        sync::{Arc, Mutex},
+    };
+
+    use super::{execute_with_input, file_name, interpret_self_dependent};
+    use crate::{
+        EvalOptions, OutputResult,
+        error::Error,
+        evaluate_code_with,
+        interpreter::{Backend, ValueResult},
+        limits::Limits,
+        number::Number,
+        value::ValueRef,
+    };
+
+    fn evaluate_file_at(file: &str) -> ValueResult {
+        execute_file_at(file).map(|(value, _)| value)
+    }
+
+    fn execute_file_at(file: &str) -> OutputResult {
+        execute_with_limits(file, Limits::default())
+    }
+
+    fn execute_with_limits(file: &str, limits: Limits) -> OutputResult {
+        let mut output = Vec::new();
+        let cursor = Cursor::new(&mut output);
+        let code = read_to_string(file).unwrap();
+        evaluate_code_with(
+            &code,
+            Arc::new(Mutex::new(Box::new(stdin()))),
+            Arc::new(Mutex::new(Box::new(cursor))),
+            PathBuf::from(file).parent().unwrap().to_path_buf(),
+            EvalOptions {
+                backend: Backend::Bytecode,
+                limits,
+                ..Default::default()
+            },
+        )
+        .map(move |val| (val, String::from_utf8(output).unwrap()))
+    }
+
+    expect_value!(fact, ValueRef::Number(Number::Integer(120)));
+    expect_value!(logic, ValueRef::Number(Number::Integer(1)));
+
+    expect_error!(error_undefined, Error::IdentifierIsNotDefined(_));
+
+    expect_output!(hello_world, "Hello World\n");
+
+    expect_value!(arithmetic, ValueRef::Number(Number::Integer(10)));
+
+    expect_value!(comparison, ValueRef::Number(Number::Integer(1052)));
+
+    expect_output!(
+        integers,
+        "9000000000000000001\n-2.5\n-2\nMinusOneHundredTwentyThree\n0.5\n90000000000000000000\n"
+    );
+
+    expect_output!(
+        big_integers,
+        "10333147966386144929666651337523200000000\nOneSextillion\n35\n"
+    );
+
+    expect_value!(simple_fib, ValueRef::Number(Number::Integer(377)));
+
+    expect_value!(tuple_fib, ValueRef::Number(Number::Integer(12586269025)));
+
+    expect_value!(looping, ValueRef::Number(Number::Integer(1)));
+
+    expect_value!(accumulate, ValueRef::Number(Number::Integer(50005000)));
+
+    expect_value!(counting, ValueRef::Number(Number::Integer(100000)));
+
+    expect_output!(lazy_unwrapping, "Once");
+
+    expect_value!(import, ValueRef::Number(Number::Integer(3)));
+
+    expect_output!(import_cache, "Loaded\n");
+
+    expect_output!(strict_arguments, "FunctionAB");
+
+    expect_value!(list, ValueRef::Number(Number::Integer(15)));
+
+    expect_output!(list_display, "[1, Two]\n");
+
+    expect_output!(list_display_lazy, "AB[3, [4]]\n{Sum: 7}\n");
+
+    expect_output!(list_equality, "EqualDifferentEqual");
+
+    expect_error!(list_empty, Error::ListIsEmpty("Head"));
+
+    expect_value!(record, ValueRef::Number(Number::Integer(16)));
+
+    expect_output!(record_display, "{Name: Marble, Tags: [1]}\n");
+
+    expect_error!(record_missing, Error::KeyNotInRecord(_));
+
+    expect_output!(
+        strings,
+        "Hello,Marble\nmarble!\nn\nContains\nDoesn't start\n"
+    );
+
+    expect_error!(
+        substring_bounds,
+        Error::IndexOutOfBounds(Number::Integer(7), 6)
+    );
+
+    expect_output!(conversion, "Half is 2.5\nTwoPointFive\n142\n0\n23\n");
+
+    expect_value!(digits, ValueRef::Number(Number::Integer(42)));
+
+    expect_error!(digits_binding, Error::ExpectedIdentifierAsVariableName);
+
+    #[test]
+    fn read_input() {
+        let (_, output) =
+            execute_with_input("read_input", "Hello\r\néxy\nz", Backend::Bytecode).unwrap();
+
+        assert_eq!(output, "Reading\nHello\né\nxy\nzNone\n");
+    }
+
+    #[test]
+    fn value_depends_on_itself() {
+        assert!(matches!(
+            interpret_self_dependent(Backend::Bytecode)
+                .unwrap_err()
+                .error,
+            Error::ValueDependsOnItself
+        ));
+    }
+
+    #[test]
+    fn limits() {
+        let steps = Limits {
+            steps: Some(10_000),
+            ..Default::default()
+        };
+        let thunks = Limits {
+            thunks: Some(1_000),
+            ..Default::default()
+        };
+
+        assert!(matches!(
+            execute_with_limits(&file_name("diverging"), steps)
+                .unwrap_err()
+                .error,
+            Error::StepLimitExceeded(10_000)
+        ));
+        assert!(matches!(
+            execute_with_limits(&file_name("accumulate"), thunks)
+                .unwrap_err()
+                .error,
+            Error::ThunkLimitExceeded(1_000)
+        ));
+    }
+
+    #[test]
+    fn constant_space() {
+        let limits = Limits {
+            steps: Some(100_000),
+            thunks: Some(100),
+            ..Default::default()
+        };
+        let error = execute_with_limits(&file_name("counting"), limits).unwrap_err();
+
+        assert!(matches!(error.error, Error::StepLimitExceeded(100_000)));
+        assert!(error.trace.calls.len() < 10);
+    }
+
+    #[test]
+    fn stack_trace() {
+        let error = evaluate_file_at(&file_name("stack_trace")).unwrap_err();
+
+        assert!(matches!(
+            error.error,
+            Error::ArgumentToOperatorMustBeANumber("Add")
+        ));
+        assert_eq!(error.token.file, 1);
+        assert!(!error.trace.calls.is_empty());
+    }
+}
@@ -1,15 +1,19 @@
 use line_index::TextRange;
 
+use crate::source::FileId;
+
 #[derive(Debug, Clone, Copy, PartialEq, Default)]
 pub struct Token {
     pub token_type: TokenType,
     pub range: TextRange,
+    pub file: FileId,
 }
 
 #[derive(PartialEq, Clone, Copy, Debug, Default)]
 pub enum TokenType {
     String(bool),
-    Number(f64),
+    /// The value is parsed from the lexeme by the compiler, as big numbers can't be copied
+    Number,
     Identifier,
 
     Fn,
@@ -24,7 +28,8 @@ pub enum TokenType {
 
     Then,
 
-    Comment,
+    /// Only returned by the scanner for unterminated comments, which span the rest of the source
+    Comment(bool),
     Eof,
 
     #[default]
@@ -1,11 +1,36 @@
 use std::{
+    collections::BTreeMap,
     fmt::Display,
-    sync::{Arc, OnceLock},
+    mem,
+    sync::{Arc, Mutex},
 };
 
-use crate::{environment::EnvRef, error::Error, expr::ExprRef};
+use crate::{
+    builtin,
+    environment::{EnvRef, Environment},
+    error::{AnnotatedError, Error},
+    expr::ExprRef,
+    limits::LiveThunk,
+    number::Number,
+    token::Token,
+};
 
-pub type ValueRef = Arc<Value>;
+/// A reference to a value
+///
+/// Numbers and builtins, that have only been given a number, are small enough to be stored
+/// in place. This way arithmetic neither allocates nor counts references. All other values
+/// are shared.
+#[derive(Debug, Clone)]
+pub enum ValueRef {
+    Number(Number),
+    Partial(Partial),
+    Shared(Arc<Value>),
+}
+
+/// The fields of a record, ordered by their keys
+///
+/// The values are lazy, like the elements of a [`List`].
+pub type Record = BTreeMap<String, ValueRef>;
 
 #[derive(Debug, Clone)]
 pub enum BuiltIn {
@@ -16,60 +41,571 @@ pub enum BuiltIn {
     IsNot,
     IsNotOf(ValueRef),
     Add,
-    AddOf(f64),
     Sub,
-    SubOf(f64),
     Mul,
-    MulOf(f64),
     Div,
-    DivOf(f64),
+    Mod,
+    Pow,
+    Min,
+    Max,
+    LessThan,
+    GreaterThan,
+    Floor,
+    Ceil,
+    Round,
+    Abs,
+    Sqrt,
     Import,
+    Cons,
+    ConsOf(ValueRef),
+    Head,
+    Tail,
+    IsEmpty,
+    Length,
+    Nth,
+    Append,
+    AppendOf(ValueRef),
+    Get,
+    GetOf(String),
+    Has,
+    HasOf(String),
+    Set,
+    SetOf(String),
+    SetOfValue(String, ValueRef),
+    Keys,
+    Concat,
+    ConcatOf(String),
+    StringLength,
+    Substring,
+    SubstringOfRange(Number, Number),
+    Split,
+    SplitOf(String),
+    Join,
+    JoinOf(String),
+    /// Joins the rest of a list, once the element before it is unwrapped.
+    /// Holds the separator, the string joined so far and the rest of the list
+    JoinOnto(String, String, ValueRef),
+    Trim,
+    Contains,
+    ContainsOf(String),
+    StartsWith,
+    StartsWithOf(String),
+    Upper,
+    Lower,
+    CharCode,
+    FromCharCode,
+    ToString,
+    ToWords,
+    ParseNumber,
+    ParseWords,
+    ReadLine,
+    ReadChar,
+    ReadAll,
+    /// Calls the builtin with the value, once all elements of its lists and records are unwrapped.
+    /// Holds the builtin, the value and the elements, that are left to unwrap
+    UnwrapFor(Box<BuiltIn>, ValueRef, Arc<Mutex<Vec<ValueRef>>>),
+}
+
+/// A builtin, that has been given a number as its first argument, like `Add of One`
+///
+/// `Is` and `IsNot` are only stored like this, if they have been given a number,
+/// otherwise they are a [`BuiltIn::IsOf`] or [`BuiltIn::IsNotOf`].
+/// All of these force their next argument.
+#[derive(Debug, Clone)]
+pub enum Partial {
+    IsOf(Number),
+    IsNotOf(Number),
+    AddOf(Number),
+    SubOf(Number),
+    MulOf(Number),
+    DivOf(Number),
+    ModOf(Number),
+    PowOf(Number),
+    MinOf(Number),
+    MaxOf(Number),
+    LessThanOf(Number),
+    GreaterThanOf(Number),
+    NthOf(Number),
+    SubstringOf(Number),
+}
+
+/// A value, that can be called with an argument
+pub enum Callable<'a> {
+    Fn(&'a ExprRef, &'a EnvRef),
+    Builtin(&'a BuiltIn),
+    Partial(&'a Partial),
 }
 
+impl Callable<'_> {
+    /// Whether the function needs its argument unwrapped. Otherwise a lazy argument is passed as is
+    pub fn forces_argument(&self) -> bool {
+        match self {
+            Callable::Fn(_, _) => false,
+            Callable::Builtin(built_in) => built_in.forces_argument(),
+            Callable::Partial(_) => true,
+        }
+    }
+}
+
+impl BuiltIn {
+    /// The number of consecutive arguments, that are forced by the builtin
+    /// and the partially applied builtins it returns
+    pub fn forced_arguments(&self) -> usize {
+        match self {
+            BuiltIn::Is
+            | BuiltIn::IsNot
+            | BuiltIn::Add
+            | BuiltIn::Sub
+            | BuiltIn::Mul
+            | BuiltIn::Div
+            | BuiltIn::Mod
+            | BuiltIn::Pow
+            | BuiltIn::Min
+            | BuiltIn::Max
+            | BuiltIn::LessThan
+            | BuiltIn::GreaterThan
+            | BuiltIn::Nth
+            | BuiltIn::Append
+            | BuiltIn::Get
+            | BuiltIn::Has
+            | BuiltIn::Concat
+            | BuiltIn::Split
+            | BuiltIn::Join
+            | BuiltIn::Contains
+            | BuiltIn::StartsWith => 2,
+            BuiltIn::Substring => 3,
+            BuiltIn::Cons => 0,
+            _ => 1,
+        }
+    }
+
+    /// Whether the builtin needs its argument unwrapped. Otherwise a lazy argument is passed as is
+    pub fn forces_argument(&self) -> bool {
+        !matches!(self, BuiltIn::Cons | BuiltIn::SetOf(_))
+    }
+}
+
+/// A persistent linked list
+///
+/// The elements are lazy, like the arguments of a call, but the list itself is always unwrapped.
+/// Lists share their tails, so prepending an element or taking the tail doesn't copy the list.
+#[derive(Debug, Clone)]
+pub enum List {
+    Empty,
+    /// The first element and the rest of the list, which is always a [`Value::List`]
+    Cons(ValueRef, ValueRef),
+}
+
+impl List {
+    pub fn iter(&self) -> ListIter<'_> {
+        ListIter { list: self }
+    }
+
+    /// Creates a list from the elements, followed by the tail
+    pub fn prepend(elements: &[ValueRef], tail: ValueRef) -> ValueRef {
+        elements.iter().rev().fold(tail, |tail, element| {
+            Value::List(List::Cons(element.clone(), tail)).new_ref()
+        })
+    }
+}
+
+pub struct ListIter<'a> {
+    list: &'a List,
+}
+
+impl<'a> Iterator for ListIter<'a> {
+    type Item = &'a ValueRef;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let List::Cons(head, tail) = self.list else {
+            return None;
+        };
+
+        let Some(Value::List(tail)) = tail.shared() else {
+            unreachable!("The tail of a list is a list")
+        };
+
+        self.list = tail;
+        Some(head)
+    }
+}
+
+/// A call, that is only evaluated once its value is needed
+#[derive(Debug)]
+pub struct Thunk {
+    state: Mutex<ThunkState>,
+    live: Option<LiveThunk>,
+}
+
+#[derive(Debug, Clone)]
+pub enum ThunkState {
+    /// The call and the environment it is evaluated in
+    Pending(ExprRef, EnvRef),
+    /// The call is being evaluated and has been taken out of the lazy call.
+    /// Holds the token of the called function, to report the lazy call being forced again
+    Evaluating(Token),
+    /// The value of the call. The call and its environment have been released
+    Evaluated(ValueRef),
+}
+
+impl Thunk {
+    pub fn new(call: ExprRef, environment: EnvRef) -> Self {
+        Self {
+            state: Mutex::new(ThunkState::Pending(call, environment)),
+            live: None,
+        }
+    }
+
+    /// Counts the lazy call as alive, until it is dropped
+    pub fn counted(mut self, live: LiveThunk) -> Self {
+        self.live = Some(live);
+        self
+    }
+
+    pub fn state(&self) -> ThunkState {
+        self.state.lock().unwrap().clone()
+    }
+
+    /// Returns the value of the call, if it has been evaluated
+    pub fn value(&self) -> Option<ValueRef> {
+        match &*self.state.lock().unwrap() {
+            ThunkState::Evaluated(value) => Some(value.clone()),
+            ThunkState::Pending(_, _) | ThunkState::Evaluating(_) => None,
+        }
+    }
+
+    /// Takes the call and its environment out of the lazy call, to evaluate them
+    ///
+    /// The lazy call is marked as being evaluated, until its value is stored. This way it
+    /// doesn't keep the environment alive while the call is evaluated, and forcing it again
+    /// in the meantime fails, instead of never finishing.
+    pub fn take(&self) -> Result<ThunkState, AnnotatedError> {
+        let mut state = self.state.lock().unwrap();
+
+        match &*state {
+            ThunkState::Pending(call, _) => {
+                let evaluating = ThunkState::Evaluating(call.callee_token());
+                Ok(mem::replace(&mut *state, evaluating))
+            }
+            ThunkState::Evaluating(token) => Err(Error::ValueDependsOnItself.annotate(*token)),
+            ThunkState::Evaluated(_) => Ok(state.clone()),
+        }
+    }
+
+    /// Stores the value of the call and releases the call and its environment
+    ///
+    /// Returns the value of the call, which is the first value that was stored
+    pub fn memoize(&self, value: ValueRef) -> ValueRef {
+        let mut state = self.state.lock().unwrap();
+
+        if let ThunkState::Evaluated(value) = &*state {
+            return value.clone();
+        }
+
+        *state = ThunkState::Evaluated(value.clone());
+        value
+    }
+}
+
+/// A value, that is shared by all references to it, see [`ValueRef`]
 #[derive(Debug)]
 pub enum Value {
-    Number(f64),
     String(String),
     Unit,
-    LazyCall(ExprRef, ExprRef, EnvRef, OnceLock<ValueRef>),
+    LazyCall(Thunk),
     Fn(ExprRef, EnvRef),
     Builtin(BuiltIn),
+    List(List),
+    Record(Record),
 }
 
-impl Value {
-    pub fn number_for_operator(&self, operator: &'static str) -> Result<f64, Error> {
+impl ValueRef {
+    /// Returns the shared value, unless the value is stored in place
+    pub fn shared(&self) -> Option<&Value> {
+        match self {
+            ValueRef::Shared(value) => Some(value),
+            ValueRef::Number(_) | ValueRef::Partial(_) => None,
+        }
+    }
+
+    /// Returns the lazy call, if the value is one
+    pub fn thunk(&self) -> Option<&Thunk> {
+        match self.shared()? {
+            Value::LazyCall(thunk) => Some(thunk),
+            _ => None,
+        }
+    }
+
+    /// Returns the function, builtin or partially applied builtin, if the value can be called
+    pub fn callable(&self) -> Option<Callable<'_>> {
+        match self {
+            ValueRef::Partial(partial) => Some(Callable::Partial(partial)),
+            ValueRef::Shared(value) => match value.as_ref() {
+                Value::Fn(body, env) => Some(Callable::Fn(body, env)),
+                Value::Builtin(built_in) => Some(Callable::Builtin(built_in)),
+                _ => None,
+            },
+            ValueRef::Number(_) => None,
+        }
+    }
+
+    /// Whether both refer to the same shared value. Values stored in place are never the same
+    pub fn ptr_eq(lhs: &ValueRef, rhs: &ValueRef) -> bool {
+        match (lhs, rhs) {
+            (ValueRef::Shared(lhs), ValueRef::Shared(rhs)) => Arc::ptr_eq(lhs, rhs),
+            _ => false,
+        }
+    }
+
+    /// The number of references to the shared value. A value stored in place is only referenced once
+    pub fn strong_count(value: &ValueRef) -> usize {
+        match value {
+            ValueRef::Shared(value) => Arc::strong_count(value),
+            ValueRef::Number(_) | ValueRef::Partial(_) => 1,
+        }
+    }
+
+    /// Returns the shared value, if this was its last reference
+    pub fn into_inner(value: ValueRef) -> Option<Value> {
+        match value {
+            ValueRef::Shared(value) => Arc::into_inner(value),
+            ValueRef::Number(_) | ValueRef::Partial(_) => None,
+        }
+    }
+
+    pub fn number_for_operator(&self, operator: &'static str) -> Result<&Number, Error> {
         match self {
-            Value::Number(f) => Ok(*f),
+            ValueRef::Number(number) => Ok(number),
             _ => Err(Error::ArgumentToOperatorMustBeANumber(operator)),
         }
     }
 
+    pub fn list_for_operator(&self, operator: &'static str) -> Result<&List, Error> {
+        match self.shared() {
+            Some(Value::List(list)) => Ok(list),
+            _ => Err(Error::ArgumentToOperatorMustBeAList(operator)),
+        }
+    }
+
+    pub fn string_for_operator(&self, operator: &'static str) -> Result<String, Error> {
+        match self.shared() {
+            Some(Value::String(string)) => Ok(string.clone()),
+            _ => Err(Error::ArgumentToOperatorMustBeAString(operator)),
+        }
+    }
+
+    pub fn record_for_operator(&self, operator: &'static str) -> Result<&Record, Error> {
+        match self.shared() {
+            Some(Value::Record(record)) => Ok(record),
+            _ => Err(Error::ArgumentToOperatorMustBeARecord(operator)),
+        }
+    }
+
+    /// Compares two unwrapped values structurally
+    ///
+    /// The elements of lists and records must have been unwrapped as well, as forcing them
+    /// isn't possible here. Elements, that haven't been evaluated, are only equal to themselves.
+    pub fn equals(&self, other: &ValueRef) -> bool {
+        match (self, other) {
+            (ValueRef::Number(l0), ValueRef::Number(r0)) => l0 == r0,
+            (ValueRef::Shared(l0), ValueRef::Shared(r0)) => match (l0.as_ref(), r0.as_ref()) {
+                (Value::String(l0), Value::String(r0)) => l0 == r0,
+                (Value::Unit, Value::Unit) => true,
+                (Value::List(l0), Value::List(r0)) => {
+                    l0.iter().count() == r0.iter().count()
+                        && l0
+                            .iter()
+                            .zip(r0.iter())
+                            .all(|(l, r)| Self::elements_equal(l, r))
+                }
+                (Value::Record(l0), Value::Record(r0)) => {
+                    l0.len() == r0.len()
+                        && l0
+                            .iter()
+                            .zip(r0.iter())
+                            .all(|((lk, l), (rk, r))| lk == rk && Self::elements_equal(l, r))
+                }
+                _ => false,
+            },
+            _ => false,
+        }
+    }
+
+    fn elements_equal(lhs: &ValueRef, rhs: &ValueRef) -> bool {
+        let unwrap = |value: &ValueRef| match value.thunk() {
+            Some(thunk) => thunk.value(),
+            None => Some(value.clone()),
+        };
+
+        if ValueRef::ptr_eq(lhs, rhs) {
+            return true;
+        }
+
+        match (unwrap(lhs), unwrap(rhs)) {
+            (Some(lhs), Some(rhs)) => lhs.equals(&rhs),
+            _ => false,
+        }
+    }
+
     pub fn get_type(&self) -> &'static str {
         match self {
-            Value::Number(_) => "Number",
-            Value::String(_) => "String",
-            Value::Unit => "Unit",
-            Value::LazyCall(_, _, _, _) => "Lazy",
-            Value::Fn(_, _) => "Function",
-            Value::Builtin(_) => "Builtin",
+            ValueRef::Number(_) => "Number",
+            ValueRef::Partial(_) => "Builtin",
+            ValueRef::Shared(value) => match value.as_ref() {
+                Value::String(_) => "String",
+                Value::Unit => "Unit",
+                Value::LazyCall(_) => "Lazy",
+                Value::Fn(_, _) => "Function",
+                Value::Builtin(_) => "Builtin",
+                Value::List(_) => "List",
+                Value::Record(_) => "Record",
+            },
         }
     }
+}
 
+impl Value {
     #[inline]
     pub fn new_ref(self) -> ValueRef {
-        ValueRef::new(self)
+        ValueRef::Shared(Arc::new(self))
+    }
+}
+
+impl Value {
+    /// Takes the environment and memoized value out of a value, leaving an empty root behind
+    fn take_references(&mut self, environments: &mut Vec<EnvRef>, values: &mut Vec<ValueRef>) {
+        match self {
+            Value::LazyCall(thunk) => match thunk.state.get_mut().unwrap() {
+                ThunkState::Pending(_, env) => {
+                    environments.push(mem::replace(env, Environment::root()))
+                }
+                ThunkState::Evaluated(value) => {
+                    values.push(mem::replace(value, builtin::UNIT.clone()))
+                }
+                ThunkState::Evaluating(_) => {}
+            },
+            Value::Fn(_, env) => environments.push(mem::replace(env, Environment::root())),
+            Value::List(list) => {
+                if let List::Cons(head, tail) = mem::replace(list, List::Empty) {
+                    values.push(head);
+                    values.push(tail);
+                }
+            }
+            Value::Record(record) => values.extend(mem::take(record).into_values()),
+            _ => {}
+        }
+    }
+}
+
+impl Drop for Value {
+    /// Lazy calls and functions capture environments, which in turn hold values, and lists hold their tails.
+    /// A recursive marble program builds long chains of these, which would overflow
+    /// the stack if they were dropped recursively, so they are taken apart iteratively.
+    fn drop(&mut self) {
+        let mut environments = Vec::new();
+        let mut values = Vec::new();
+
+        self.take_references(&mut environments, &mut values);
+
+        loop {
+            if let Some(env) = environments.pop() {
+                match EnvRef::into_inner(env) {
+                    Some(Environment::Captures(captured)) => values.extend(captured),
+                    Some(Environment::Call { argument, captures }) => {
+                        values.push(argument);
+                        environments.push(captures);
+                    }
+                    None => {}
+                }
+            } else if let Some(value) = values.pop() {
+                if let Some(mut value) = ValueRef::into_inner(value) {
+                    value.take_references(&mut environments, &mut values);
+                }
+            } else {
+                break;
+            }
+        }
+    }
+}
+
+impl Display for ValueRef {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ValueRef::Number(n) => f.write_fmt(format_args!("{n}")),
+            ValueRef::Partial(p) => f.write_fmt(format_args!("Builtin {p}")),
+            ValueRef::Shared(value) => value.fmt(f),
+        }
+    }
+}
+
+impl Display for Partial {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (name, n) = match self {
+            Partial::IsOf(n) => ("IsOf", n),
+            Partial::IsNotOf(n) => ("IsNotOf", n),
+            Partial::AddOf(n) => ("AddOf", n),
+            Partial::SubOf(n) => ("SubOf", n),
+            Partial::MulOf(n) => ("MulOf", n),
+            Partial::DivOf(n) => ("DivOf", n),
+            Partial::ModOf(n) => ("ModOf", n),
+            Partial::PowOf(n) => ("PowOf", n),
+            Partial::MinOf(n) => ("MinOf", n),
+            Partial::MaxOf(n) => ("MaxOf", n),
+            Partial::LessThanOf(n) => ("LessThanOf", n),
+            Partial::GreaterThanOf(n) => ("GreaterThanOf", n),
+            Partial::NthOf(n) => ("NthOf", n),
+            Partial::SubstringOf(n) => ("SubstringOf", n),
+        };
+
+        f.write_fmt(format_args!("{name} {n}"))
     }
 }
 
 impl Display for Value {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Value::Number(n) => f.write_fmt(format_args!("{n}")),
             Value::String(s) => f.write_fmt(format_args!("{s}")),
             Value::Unit => f.write_str("Unit"),
-            Value::LazyCall(_, _, _, _) => f.write_str("Lazy"),
+            Value::LazyCall(_) => f.write_str("Lazy"),
             Value::Fn(_, _) => f.write_str("Function"),
             Value::Builtin(b) => f.write_fmt(format_args!("Builtin {b:?}")),
+            Value::List(list) => {
+                f.write_str("[")?;
+
+                for (idx, element) in list.iter().enumerate() {
+                    if idx > 0 {
+                        f.write_str(", ")?;
+                    }
+
+                    Self::fmt_element(element, f)?;
+                }
+
+                f.write_str("]")
+            }
+            Value::Record(record) => {
+                f.write_str("{")?;
+
+                for (idx, (key, value)) in record.iter().enumerate() {
+                    if idx > 0 {
+                        f.write_str(", ")?;
+                    }
+
+                    f.write_fmt(format_args!("{key}: "))?;
+                    Self::fmt_element(value, f)?;
+                }
+
+                f.write_str("}")
+            }
+        }
+    }
+}
+
+impl Value {
+    /// Elements, that have been evaluated, are shown with their value
+    fn fmt_element(element: &ValueRef, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match element.thunk().and_then(Thunk::value) {
+            Some(value) => value.fmt(f),
+            None => element.fmt(f),
         }
     }
 }
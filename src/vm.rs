@@ -0,0 +1,252 @@
+//! A stack based virtual machine, that executes [`Code`]
+//!
+//! The virtual machine shares its values with the tree walking interpreter: Functions are
+//! [`Value::Fn`]s and lazy calls are [`Value::LazyCall`]s. The code of a function body or a
+//! lazy call is compiled the first time it is executed and then cached by the interpreter.
+//! This way values created by the builtins or by imported modules can be called and forced
+//! by the virtual machine just like its own values.
+//!
+//! Calls and forced lazy calls push a new [`Frame`] instead of recursing, so the depth of
+//! recursion is only bounded by the available heap memory. Calls and forced lazy calls in
+//! tail position replace the current frame instead, so loops execute in constant space.
+//!
+//! Errors are annotated with the calls of all frames as their stack trace. As tail calls
+//! replace the frame of their caller, only the last call of a loop shows up in the trace.
+
+use std::mem;
+
+use crate::{
+    bytecode::{Code, CodeRef, Op},
+    environment::{EnvRef, Environment},
+    error::{AnnotatedError, Error},
+    expr::{Expr, ExprRef},
+    interpreter::{Interpreter, ValueResult},
+    token::Token,
+    value::{Callable, ThunkState, Value, ValueRef},
+};
+
+/// The execution state of a single [`Code`]
+struct Frame {
+    code: CodeRef,
+    ip: usize,
+    env: EnvRef,
+    /// The height of the stack when the frame was entered
+    base: usize,
+    /// The lazy calls, that receive the returned value
+    updates: Vec<ValueRef>,
+    /// The token of the called function, that the frame was entered for
+    call: Token,
+}
+
+impl Frame {
+    fn new(code: CodeRef, env: EnvRef, base: usize, call: Token) -> Self {
+        Self {
+            code,
+            ip: 0,
+            env,
+            base,
+            updates: Vec::new(),
+            call,
+        }
+    }
+
+    /// Whether the frame returns right after the current op
+    fn is_tail(&self) -> bool {
+        self.code.ops[self.ip] == Op::Return
+    }
+}
+
+struct Machine {
+    stack: Vec<ValueRef>,
+    frames: Vec<Frame>,
+    frame: Frame,
+}
+
+impl Machine {
+    /// Starts executing the code, optionally updating a lazy call with the returned value
+    ///
+    /// If the current frame would return the value of the code right away, it is replaced
+    /// instead. This way tail calls execute in constant space.
+    fn enter(&mut self, code: CodeRef, env: EnvRef, lazy: Option<ValueRef>, call: Token) {
+        if self.frame.is_tail() {
+            self.stack.truncate(self.frame.base);
+            self.frame.code = code;
+            self.frame.ip = 0;
+            self.frame.env = env;
+            self.frame.call = call;
+        } else {
+            let frame = Frame::new(code, env, self.stack.len(), call);
+            self.frames.push(mem::replace(&mut self.frame, frame));
+        }
+
+        // If nothing else references the lazy call, its value can never be observed again
+        if let Some(lazy) = lazy.filter(|lazy| ValueRef::strong_count(lazy) > 1) {
+            self.frame.updates.push(lazy);
+        }
+    }
+
+    fn pop(&mut self) -> ValueRef {
+        self.stack.pop().expect("Popped empty stack")
+    }
+
+    /// Returns the calls of all frames, the innermost call first
+    fn trace(&self) -> Vec<Token> {
+        let callers = self.frames.iter().rev().map(|frame| frame.call);
+        [self.frame.call].into_iter().chain(callers).collect()
+    }
+}
+
+impl Interpreter<'_> {
+    /// Executes the code and returns the unwrapped value it evaluates to
+    pub(crate) fn run(&mut self, code: CodeRef) -> ValueResult {
+        let mut machine = Machine {
+            stack: Vec::new(),
+            frames: Vec::new(),
+            frame: Frame::new(code, Environment::root(), 0, Token::default()),
+        };
+
+        self.execute(&mut machine)
+            .map_err(|err| err.with_trace(machine.trace()))
+    }
+
+    fn execute(&mut self, machine: &mut Machine) -> ValueResult {
+        loop {
+            let frame = &mut machine.frame;
+            let op = frame.code.ops[frame.ip];
+            let token = frame.code.tokens[frame.ip];
+            frame.ip += 1;
+
+            self.budget.step().map_err(|err| err.annotate(token))?;
+
+            let frame = &mut machine.frame;
+
+            match op {
+                Op::Load(depth) => {
+                    let value = frame.env.find(depth);
+                    machine.stack.push(value);
+                }
+                Op::Constant(idx) => {
+                    let value = frame.code.constants[idx].clone();
+                    machine.stack.push(value);
+                }
+                Op::Closure(idx) => {
+                    let Expr::Fn(body, captures) = frame.code.exprs[idx].expr() else {
+                        unreachable!("Closures are created from functions")
+                    };
+
+                    let value = Value::Fn(body.clone(), frame.env.capture(captures));
+                    machine.stack.push(value.new_ref());
+                }
+                Op::Thunk(idx) => {
+                    let value = self
+                        .budget
+                        .thunk(frame.code.exprs[idx].clone(), frame.env.clone())
+                        .map_err(|err| err.annotate(token))?;
+                    machine.stack.push(Value::LazyCall(value).new_ref());
+                }
+                Op::Force => {
+                    let value = machine.pop();
+                    self.force(machine, value)?;
+                }
+                Op::Apply => {
+                    let argument = machine.pop();
+                    let function = machine.pop();
+
+                    match function.callable() {
+                        Some(Callable::Fn(body, env)) => {
+                            let code = self.code_for(body);
+                            let env = Environment::extend(env.clone(), argument);
+                            machine.enter(code, env, None, token);
+                        }
+                        Some(callable) => {
+                            let argument = match Self::forced(&argument) {
+                                Some(value) => value,
+                                None if !callable.forces_argument() => argument,
+                                None => {
+                                    // The call is repeated, once the argument is unwrapped
+                                    machine.frame.ip -= 1;
+                                    machine.stack.push(function.clone());
+                                    self.force(machine, argument)?;
+                                    continue;
+                                }
+                            };
+
+                            let value = match callable {
+                                Callable::Builtin(built_in) => {
+                                    self.evaluate_builtin(built_in, argument)
+                                }
+                                Callable::Partial(partial) => {
+                                    Self::evaluate_partial(partial, argument)
+                                }
+                                Callable::Fn(_, _) => unreachable!("Functions are entered"),
+                            }
+                            .map_err(|err| err.annotate(token))?;
+                            self.force(machine, value)?;
+                        }
+                        None => return Err(Error::ValueNotCallable(function).annotate(token)),
+                    }
+                }
+                Op::Return => {
+                    let value = machine.pop();
+                    machine.stack.truncate(machine.frame.base);
+
+                    for lazy in &machine.frame.updates {
+                        let Some(thunk) = lazy.thunk() else {
+                            unreachable!("Only lazy calls are updated")
+                        };
+
+                        thunk.memoize(value.clone());
+                    }
+
+                    let Some(frame) = machine.frames.pop() else {
+                        return Ok(value);
+                    };
+
+                    machine.frame = frame;
+                    machine.stack.push(value);
+                }
+            }
+        }
+    }
+
+    /// Pushes the unwrapped value, entering the code of a lazy call if it wasn't forced before
+    ///
+    /// Fails, if the lazy call is already being evaluated, as its value depends on itself
+    fn force(&mut self, machine: &mut Machine, value: ValueRef) -> Result<(), AnnotatedError> {
+        if let Some(value) = Self::forced(&value) {
+            machine.stack.push(value);
+            return Ok(());
+        }
+
+        let Some(thunk) = value.thunk() else {
+            unreachable!("Only lazy calls are forced")
+        };
+
+        let ThunkState::Pending(call, env) = thunk.take()? else {
+            unreachable!("Lazy call was already forced")
+        };
+
+        let code = self.code_for(&call);
+        machine.enter(code, env, Some(value), call.callee_token());
+
+        Ok(())
+    }
+
+    /// Returns the unwrapped value, unless it is a lazy call, that hasn't been forced yet
+    fn forced(value: &ValueRef) -> Option<ValueRef> {
+        match value.thunk() {
+            Some(thunk) => thunk.value(),
+            None => Some(value.clone()),
+        }
+    }
+
+    /// Returns the compiled code of a function body or a lazy call
+    fn code_for(&mut self, expr: &ExprRef) -> CodeRef {
+        // The expression is stored with its code, so that its address isn't reused
+        self.compiled
+            .entry(ExprRef::as_ptr(expr) as usize)
+            .or_insert_with(|| (expr.clone(), Code::compile(expr).new_ref()))
+            .1
+            .clone()
+    }
+}